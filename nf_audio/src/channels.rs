@@ -0,0 +1,102 @@
+use std::str::FromStr;
+
+/// Which part of a multichannel stream is passed on.  The processing
+/// works on a single channel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChannelSelection {
+    /// A single channel, counting from 0.
+    Channel(usize),
+    /// The mean of all channels.
+    Downmix,
+}
+
+impl Default for ChannelSelection {
+    fn default() -> Self {
+        ChannelSelection::Channel(0)
+    }
+}
+
+impl FromStr for ChannelSelection {
+    type Err = String;
+
+    /// Parses "downmix" or a channel index.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "downmix" => Ok(ChannelSelection::Downmix),
+            other => other
+                .parse()
+                .map(ChannelSelection::Channel)
+                .map_err(|_| format!("Invalid channel selection: {}", other)),
+        }
+    }
+}
+
+impl ChannelSelection {
+    /// Turns interleaved samples with the given number of channels
+    /// into mono samples.  Selecting a channel that doesn't exist
+    /// falls back to the last channel.
+    pub fn to_mono(&self, data: &[f32], channels: usize) -> Vec<f32> {
+        data.chunks(channels)
+            .map(|frame| self.mono_sample(frame.len(), |c| frame[c]))
+            .collect()
+    }
+
+    /// The mono sample of a single frame, with `sample` giving the
+    /// sample of each of the channels.  For sources that keep the
+    /// channels apart and can't allocate, like the JACK callback.
+    pub fn mono_sample<F: Fn(usize) -> f32>(&self, channels: usize, sample: F) -> f32 {
+        match self {
+            ChannelSelection::Channel(c) => sample((*c).min(channels - 1)),
+            ChannelSelection::Downmix => (0..channels).map(sample).sum::<f32>() / channels as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three stereo frames.
+    const STEREO: [f32; 6] = [1., 0., 0.5, -0.5, -1., 1.];
+
+    #[test]
+    fn parse() {
+        assert_eq!("downmix".parse(), Ok(ChannelSelection::Downmix));
+        assert_eq!("1".parse(), Ok(ChannelSelection::Channel(1)));
+        assert!("left".parse::<ChannelSelection>().is_err());
+    }
+
+    #[test]
+    fn select_a_channel() {
+        assert_eq!(
+            ChannelSelection::Channel(0).to_mono(&STEREO, 2),
+            [1., 0.5, -1.]
+        );
+        assert_eq!(
+            ChannelSelection::Channel(1).to_mono(&STEREO, 2),
+            [0., -0.5, 1.]
+        );
+    }
+
+    #[test]
+    fn missing_channel_falls_back_to_the_last() {
+        assert_eq!(
+            ChannelSelection::Channel(5).to_mono(&STEREO, 2),
+            [0., -0.5, 1.]
+        );
+    }
+
+    #[test]
+    fn downmix() {
+        assert_eq!(ChannelSelection::Downmix.to_mono(&STEREO, 2), [0.5, 0., 0.]);
+        let surround = [0.5; 6];
+        assert_eq!(ChannelSelection::Downmix.to_mono(&surround, 6), [0.5]);
+    }
+
+    #[test]
+    fn mono_passes_through() {
+        let mono = [0.1, 0.2, 0.3];
+        assert_eq!(ChannelSelection::Downmix.to_mono(&mono, 1), mono);
+        assert_eq!(ChannelSelection::default().to_mono(&mono, 1), mono);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{get_host, resample_if_needed, AudioSource, ChannelSelection, ValsHandler};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, SampleFormat, StreamConfig, SupportedStreamConfig};
+use log::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+pub struct CpalAudioGetter {
+    dev: cpal::Device,
+    config: SupportedStreamConfig,
+    stream: Option<cpal::Stream>,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    failed: Arc<AtomicBool>,
+}
+
+impl CpalAudioGetter {
+    pub fn new(dev_name: String) -> CpalAudioGetter {
+        CpalAudioGetter::try_new(dev_name).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Opens the device, or returns an error if it is not available,
+    /// for example because it is unplugged.
+    pub fn try_new(dev_name: String) -> Result<CpalAudioGetter, String> {
+        let host = get_host();
+        // Setup the input device and stream with the default input config.
+        let device = if dev_name == "default" {
+            host.default_input_device()
+        } else {
+            host.input_devices()
+                .map_err(|e| format!("Failed to scan input devices: {}", e))?
+                .find(|x| x.name().map(|y| y == dev_name).unwrap_or(false))
+        }
+        .ok_or_else(|| format!("failed to find input device: {}", dev_name))?;
+        info!("Selected input device: {}", device.name().unwrap_or_default());
+        let config = device
+            .default_input_config()
+            .map_err(|e| format!("Failed to get default input config: {}", e))?;
+        info!("Selected config: {:?}", config);
+        Ok(CpalAudioGetter {
+            dev: device,
+            config,
+            stream: None,
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            failed: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// The sample rate of the device.
+    pub fn get_device_sample_rate(&self) -> f32 {
+        self.config.sample_rate().0 as f32
+    }
+
+    /// Builds an input stream for a sample type.  The samples are
+    /// normalized to [-1, 1] and reduced to a single channel.
+    fn build_stream<T: cpal::Sample>(
+        &self,
+        mut vals_handler: Box<dyn ValsHandler>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+        let failed = Arc::clone(&self.failed);
+        let err_fn = move |err| {
+            warn!("an error occurred on stream: {}", err);
+            failed.store(true, Ordering::SeqCst);
+        };
+        let channels = self.config.channels() as usize;
+        let channel_selection = self.channel_selection;
+        self.dev.build_input_stream(
+            &StreamConfig {
+                channels: self.config.channels(),
+                sample_rate: self.config.sample_rate(),
+                buffer_size: BufferSize::Default,
+            },
+            move |data: &[T], _: &_| {
+                debug!("Received {:?} samples.", data.len());
+                let b_new = mono_samples(data, channels, channel_selection);
+                vals_handler.take_frame(b_new.as_slice());
+            },
+            err_fn,
+        )
+    }
+
+}
+
+/// Normalizes the samples to [-1, 1] and reduces them to a single
+/// channel.
+fn mono_samples<T: cpal::Sample>(
+    data: &[T],
+    channels: usize,
+    channel_selection: ChannelSelection,
+) -> Vec<f32> {
+    let samples: Vec<f32> = data.iter().map(|v| v.to_f32()).collect();
+    channel_selection.to_mono(&samples, channels)
+}
+
+impl AudioSource for CpalAudioGetter {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate
+            .unwrap_or_else(|| self.get_device_sample_rate())
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        debug!("In start_processing.");
+        let vals_handler = resample_if_needed(
+            self.get_device_sample_rate(),
+            self.get_sample_rate(),
+            vals_handler,
+        );
+        info!(
+            "Building input stream with {:?} sample_format, {} channels.",
+            self.config.sample_format(),
+            self.config.channels()
+        );
+        let stream = match self.config.sample_format() {
+            SampleFormat::F32 => self.build_stream::<f32>(vals_handler),
+            SampleFormat::I16 => self.build_stream::<i16>(vals_handler),
+            SampleFormat::U16 => self.build_stream::<u16>(vals_handler),
+        };
+        // a failed stream is reported through has_failed, so it can be
+        // rebuilt instead of taking down the process
+        self.failed.store(false, Ordering::SeqCst);
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to open stream: {}", e);
+                self.failed.store(true, Ordering::SeqCst);
+                return;
+            }
+        };
+        info!("Starting stream.");
+        if let Err(e) = stream.play() {
+            warn!("Failed to start stream: {}", e);
+            self.failed.store(true, Ordering::SeqCst);
+        }
+        self.stream = Some(stream);
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        self.stream = None;
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn i16_samples_are_normalized() {
+        let data = [i16::MIN, 0, i16::MAX];
+        assert_eq!(
+            mono_samples(&data, 1, ChannelSelection::default()),
+            [-1., 0., 1.]
+        );
+    }
+
+    #[test]
+    fn u16_samples_are_normalized() {
+        let data = [0, 32768, u16::MAX];
+        assert_eq!(
+            mono_samples(&data, 1, ChannelSelection::default()),
+            [-1., 0., 1.]
+        );
+    }
+
+    #[test]
+    fn f32_samples_are_kept() {
+        let data = [-1., 0.25, 1.];
+        assert_eq!(mono_samples(&data, 1, ChannelSelection::default()), data);
+    }
+
+    #[test]
+    fn stereo_samples_are_reduced() {
+        let data = [i16::MAX, 0, i16::MIN, 0];
+        assert_eq!(
+            mono_samples(&data, 2, ChannelSelection::Channel(0)),
+            [1., -1.]
+        );
+        assert_eq!(
+            mono_samples(&data, 2, ChannelSelection::Downmix),
+            [0.5, -0.5]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::get_host;
+use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::SampleFormat;
+use serde::Serialize;
+
+/// An audio input that can be used as a source.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    /// The audio system, e.g. `ALSA` or `JACK`.
+    pub host: String,
+    /// What to put in `audio-in` or `--input` to use this device.
+    pub spec: String,
+    pub is_default: bool,
+    pub default_config: Option<ConfigInfo>,
+    pub supported_configs: Vec<ConfigRange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigInfo {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub sample_format: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigRange {
+    pub channels: u16,
+    pub min_sample_rate: u32,
+    pub max_sample_rate: u32,
+    pub sample_format: String,
+}
+
+fn format_name(format: SampleFormat) -> String {
+    match format {
+        SampleFormat::I16 => "i16",
+        SampleFormat::U16 => "u16",
+        SampleFormat::F32 => "f32",
+    }
+    .to_string()
+}
+
+/// Lists all audio inputs with their capabilities.  Devices that fail
+/// to report their configs are still listed, without configs.
+pub fn enumerate_devices() -> Vec<DeviceInfo> {
+    let host = get_host();
+    let host_name = host.id().name().to_string();
+    let default_name = host.default_input_device().and_then(|d| d.name().ok());
+    let inputs: Vec<cpal::Device> = host
+        .input_devices()
+        .map(|devices| devices.collect())
+        .unwrap_or_default();
+    inputs
+        .into_iter()
+        .map(|dev| {
+            let name = dev.name().unwrap_or_else(|_| "NO NAME".to_string());
+            let default_config = dev.default_input_config().ok().map(|c| ConfigInfo {
+                channels: c.channels(),
+                sample_rate: c.sample_rate().0,
+                sample_format: format_name(c.sample_format()),
+            });
+            let supported_configs = dev
+                .supported_input_configs()
+                .map(|configs| {
+                    configs
+                        .map(|c| ConfigRange {
+                            channels: c.channels(),
+                            min_sample_rate: c.min_sample_rate().0,
+                            max_sample_rate: c.max_sample_rate().0,
+                            sample_format: format_name(c.sample_format()),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            DeviceInfo {
+                spec: format!("cpal:{}", name),
+                is_default: Some(&name) == default_name.as_ref(),
+                name,
+                host: host_name.clone(),
+                default_config,
+                supported_configs,
+            }
+        })
+        .chain(jack_ports())
+        .collect()
+}
+
+#[cfg(feature = "jack")]
+fn jack_ports() -> Vec<DeviceInfo> {
+    crate::jack_source::enumerate_jack_ports()
+}
+
+#[cfg(not(feature = "jack"))]
+fn jack_ports() -> Vec<DeviceInfo> {
+    vec![]
+}
+
+/// Prints the audio inputs, as JSON or one per line.
+pub fn list_devices(json: bool) {
+    let devices = enumerate_devices();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&devices).unwrap());
+        return;
+    }
+    for dev in devices {
+        let config = match dev.default_config {
+            Some(c) => format!(
+                "{} channels, {} Hz, {}",
+                c.channels, c.sample_rate, c.sample_format
+            ),
+            None => "no default config".to_string(),
+        };
+        println!(
+            "{}{} ({})",
+            dev.spec,
+            if dev.is_default { " [default]" } else { "" },
+            config
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use crate::{try_open_source, AudioSource, ChannelSelection, SourceStatus, ValsHandler};
+use log::{info, warn};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use stoppable_thread::{spawn, StoppableHandle};
+
+/// How often the current source is checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// If no frame arrives for this long, the source counts as failed,
+/// unless another timeout is set.
+pub const FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+/// The wait after a round of failed attempts doubles up to the max.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Passes frames on to a handler that is shared between all sources
+/// that are opened over time, and remembers when the last one came.
+struct SharedHandler {
+    handler: Arc<Mutex<Box<dyn ValsHandler>>>,
+    last_frame: Arc<Mutex<Instant>>,
+}
+
+impl ValsHandler for SharedHandler {
+    fn take_frame(&mut self, frame: &[f32]) {
+        *self.last_frame.lock().unwrap() = Instant::now();
+        self.handler.lock().unwrap().take_frame(frame);
+    }
+}
+
+/// Tries a list of sources in order and uses the first one that can
+/// be opened.  If the stream fails or stops delivering frames, it is
+/// closed and the list is tried again, with a growing pause between
+/// rounds.  The ValsHandler is told about this through
+/// `status_changed`, so it can show that there is no audio.
+///
+/// Once a source works, it is kept, even if one earlier in the list
+/// becomes available again.
+///
+/// All sources are resampled to the target sample rate.  Without one,
+/// the sources are probed for their rate when it is first asked for,
+/// and the rate of the first one that can be opened is used.
+pub struct FallbackSource {
+    specs: Vec<String>,
+    device_sample_rate: Cell<Option<f32>>,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    frame_timeout: Option<Duration>,
+    finished: Arc<AtomicBool>,
+    thread: Option<StoppableHandle<()>>,
+}
+
+impl FallbackSource {
+    /// Takes source specs as accepted by `open_source`, the first one
+    /// is preferred.  Nothing is opened until it is needed.
+    pub fn new(specs: Vec<String>) -> FallbackSource {
+        assert!(!specs.is_empty(), "No audio sources given");
+        FallbackSource {
+            specs,
+            device_sample_rate: Cell::new(None),
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            frame_timeout: Some(FRAME_TIMEOUT),
+            finished: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+
+    /// How long a source may go without delivering a frame before it
+    /// counts as failed.  With None only sources that report a broken
+    /// stream are replaced, for senders that go quiet on purpose.
+    pub fn set_frame_timeout(&mut self, timeout: Option<Duration>) {
+        self.frame_timeout = timeout;
+    }
+
+    /// The rate of the first source that can be opened.  The source is
+    /// closed again right away.
+    fn probe_sample_rate(&self) -> f32 {
+        if let Some(rate) = self.device_sample_rate.get() {
+            return rate;
+        }
+        let rate = self
+            .specs
+            .iter()
+            .find_map(|spec| try_open_source(spec).ok())
+            .map(|source| source.get_sample_rate())
+            .unwrap_or_else(|| {
+                warn!("No audio source available, assuming 44100 Hz.");
+                44100.
+            });
+        self.device_sample_rate.set(Some(rate));
+        rate
+    }
+}
+
+/// Opens and starts the first source in the list that is available.
+fn open_first(
+    specs: &[String],
+    channel_selection: ChannelSelection,
+    sample_rate: f32,
+    handler: &Arc<Mutex<Box<dyn ValsHandler>>>,
+    last_frame: &Arc<Mutex<Instant>>,
+) -> Option<(String, Box<dyn AudioSource>)> {
+    for spec in specs {
+        match try_open_source(spec) {
+            Ok(mut source) => {
+                source.set_channel_selection(channel_selection);
+                source.set_target_sample_rate(Some(sample_rate));
+                *last_frame.lock().unwrap() = Instant::now();
+                source.start_processing(Box::new(SharedHandler {
+                    handler: Arc::clone(handler),
+                    last_frame: Arc::clone(last_frame),
+                }));
+                if !source.has_failed() {
+                    return Some((spec.clone(), source));
+                }
+                source.stop_processing();
+            }
+            Err(e) => warn!("Audio source {} not available: {}", spec, e),
+        }
+    }
+    None
+}
+
+impl AudioSource for FallbackSource {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate
+            .unwrap_or_else(|| self.probe_sample_rate())
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        let specs = self.specs.clone();
+        let channel_selection = self.channel_selection;
+        let sample_rate = self.get_sample_rate();
+        let frame_timeout = self.frame_timeout;
+        let finished = Arc::clone(&self.finished);
+        let handler = Arc::new(Mutex::new(vals_handler));
+        // The sources are opened and dropped in this thread, as cpal
+        // streams can't be moved between threads.
+        self.thread = Some(spawn(move |stopped| {
+            let last_frame = Arc::new(Mutex::new(Instant::now()));
+            let mut current: Option<(String, Box<dyn AudioSource>)> = None;
+            let mut no_audio = false;
+            let mut backoff = MIN_BACKOFF;
+            let mut t_next_try = Instant::now();
+            while !stopped.get() {
+                if let Some((spec, source)) = &mut current {
+                    if source.is_finished() {
+                        info!("Audio source {} finished.", spec);
+                        finished.store(true, Ordering::SeqCst);
+                        break;
+                    }
+                    let timed_out = match frame_timeout {
+                        Some(timeout) => last_frame.lock().unwrap().elapsed() > timeout,
+                        None => false,
+                    };
+                    if source.has_failed() || timed_out {
+                        warn!("Audio source {} failed, reopening.", spec);
+                        source.stop_processing();
+                        current = None;
+                    }
+                }
+                if current.is_none() && Instant::now() >= t_next_try {
+                    current = open_first(
+                        &specs,
+                        channel_selection,
+                        sample_rate,
+                        &handler,
+                        &last_frame,
+                    );
+                    match &current {
+                        Some((spec, _)) => {
+                            info!("Reading audio from {}.", spec);
+                            handler
+                                .lock()
+                                .unwrap()
+                                .status_changed(&SourceStatus::Active(spec.clone()));
+                            no_audio = false;
+                            backoff = MIN_BACKOFF;
+                        }
+                        None => {
+                            warn!("No audio source available, retrying in {:?}.", backoff);
+                            t_next_try = Instant::now() + backoff;
+                            backoff = (backoff * 2).min(MAX_BACKOFF);
+                        }
+                    }
+                }
+                if current.is_none() && !no_audio {
+                    handler
+                        .lock()
+                        .unwrap()
+                        .status_changed(&SourceStatus::NoAudio);
+                    no_audio = true;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+            if let Some((_, mut source)) = current {
+                source.stop_processing();
+            }
+        }));
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        if let Some(thread) = self.thread.take() {
+            thread.stop().join().unwrap();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,192 @@
+use crate::{resample_if_needed, AudioSource, ChannelSelection, ValsHandler};
+use claxon::FlacReader;
+use hound::{SampleFormat, WavReader};
+use log::info;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use stoppable_thread::{spawn, SimpleAtomicBool, StoppableHandle};
+
+/// How many samples per channel are delivered in one frame.
+const FRAME_SIZE: usize = 512;
+
+enum FileReader {
+    Wav(WavReader<BufReader<File>>),
+    Flac(FlacReader<File>),
+}
+
+/// Plays a WAV or FLAC file.  The file is decoded in a separate
+/// thread, either in real time, or as fast as the ValsHandler can
+/// take it, which is useful for offline analysis.
+pub struct FileAudioSource {
+    path: String,
+    reader: Option<FileReader>,
+    sample_rate: f32,
+    channels: usize,
+    realtime: bool,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    finished: Arc<AtomicBool>,
+    thread: Option<StoppableHandle<()>>,
+}
+
+impl FileAudioSource {
+    pub fn new(path: &str, realtime: bool) -> FileAudioSource {
+        let (reader, sample_rate, channels) = if path.to_lowercase().ends_with(".flac") {
+            let reader = FlacReader::open(path).expect("Failed to open FLAC file");
+            let info = reader.streaminfo();
+            let (sample_rate, channels) = (info.sample_rate, info.channels);
+            (FileReader::Flac(reader), sample_rate, channels as usize)
+        } else {
+            let reader = WavReader::open(path).expect("Failed to open WAV file");
+            let spec = reader.spec();
+            (FileReader::Wav(reader), spec.sample_rate, spec.channels as usize)
+        };
+        info!("Opened {} ({} Hz, {} channels).", path, sample_rate, channels);
+        FileAudioSource {
+            path: path.to_string(),
+            reader: Some(reader),
+            sample_rate: sample_rate as f32,
+            channels,
+            realtime,
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            finished: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+/// Hands the samples to the handler in frames, until the samples run
+/// out or the thread is stopped.  In real time mode, the frames are
+/// paced by the sample rate.
+fn deliver<I: Iterator<Item = f32>>(
+    samples: I,
+    channels: usize,
+    sample_rate: f32,
+    realtime: bool,
+    channel_selection: ChannelSelection,
+    vals_handler: &mut Box<dyn ValsHandler>,
+    stopped: &SimpleAtomicBool,
+) {
+    let t_start = Instant::now();
+    let mut samples_sent = 0;
+    let mut buf = Vec::with_capacity(FRAME_SIZE * channels);
+    for sample in samples {
+        buf.push(sample);
+        if buf.len() < FRAME_SIZE * channels {
+            continue;
+        }
+        vals_handler.take_frame(&channel_selection.to_mono(&buf, channels));
+        buf.clear();
+        samples_sent += FRAME_SIZE;
+        if stopped.get() {
+            return;
+        }
+        if realtime {
+            let t_due = t_start + Duration::from_secs_f32(samples_sent as f32 / sample_rate);
+            let now = Instant::now();
+            if t_due > now {
+                thread::sleep(t_due - now);
+            }
+        }
+    }
+    // remaining incomplete frame
+    let n = buf.len() - buf.len() % channels;
+    if n > 0 {
+        vals_handler.take_frame(&channel_selection.to_mono(&buf[..n], channels));
+    }
+}
+
+impl AudioSource for FileAudioSource {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        let reader = self
+            .reader
+            .take()
+            .expect("A file can only be played once");
+        let mut vals_handler =
+            resample_if_needed(self.sample_rate, self.get_sample_rate(), vals_handler);
+        let channels = self.channels;
+        let sample_rate = self.sample_rate;
+        let realtime = self.realtime;
+        let channel_selection = self.channel_selection;
+        let finished = Arc::clone(&self.finished);
+        let path = self.path.clone();
+        info!("Playing {}.", path);
+        self.thread = Some(spawn(move |stopped| {
+            match reader {
+                FileReader::Wav(reader) => {
+                    let spec = reader.spec();
+                    match spec.sample_format {
+                        SampleFormat::Float => deliver(
+                            reader.into_samples::<f32>().map(|s| s.unwrap_or(0.)),
+                            channels,
+                            sample_rate,
+                            realtime,
+                            channel_selection,
+                            &mut vals_handler,
+                            stopped,
+                        ),
+                        SampleFormat::Int => {
+                            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                            deliver(
+                                reader
+                                    .into_samples::<i32>()
+                                    .map(move |s| s.unwrap_or(0) as f32 / scale),
+                                channels,
+                                sample_rate,
+                                realtime,
+                                channel_selection,
+                                &mut vals_handler,
+                                stopped,
+                            )
+                        }
+                    }
+                }
+                FileReader::Flac(mut reader) => {
+                    let bits = reader.streaminfo().bits_per_sample;
+                    let scale = (1i64 << (bits - 1)) as f32;
+                    deliver(
+                        reader
+                            .samples()
+                            .map(move |s| s.unwrap_or(0) as f32 / scale),
+                        channels,
+                        sample_rate,
+                        realtime,
+                        channel_selection,
+                        &mut vals_handler,
+                        stopped,
+                    )
+                }
+            }
+            info!("Finished playing {}.", path);
+            finished.store(true, Ordering::SeqCst);
+        }));
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        if let Some(thread) = self.thread.take() {
+            thread.stop().join().unwrap();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,275 @@
+use crate::devices::{ConfigInfo, ConfigRange, DeviceInfo};
+use crate::{resample_if_needed, AudioSource, ChannelSelection, ValsHandler};
+use jack::{
+    AsyncClient, AudioIn, Client, ClientOptions, Control, Frames, Port, PortFlags, ProcessScope,
+};
+use log::{info, warn};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use stoppable_thread::{spawn, StoppableHandle};
+
+const CLIENT_NAME: &str = "nightfire";
+
+/// How much audio the queue between the JACK callback and the reader
+/// thread holds, in seconds.
+const QUEUE_SECS: f32 = 0.5;
+
+/// How often the reader thread looks for new samples.
+const READ_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Runs in the realtime JACK process callback.  It interleaves the
+/// ports, picks or mixes the selected channel and pushes the samples
+/// into a lock-free queue, without allocating or locking; the reader
+/// thread hands them to the ValsHandler.
+pub struct JackProcessor {
+    ports: Vec<Port<AudioIn>>,
+    channel_selection: ChannelSelection,
+    /// Sized for the JACK buffer size, outside of the process callback.
+    buf: Vec<f32>,
+    samples: Producer<f32>,
+    /// Samples that didn't fit into the queue, reported by the reader.
+    dropped: Arc<AtomicUsize>,
+}
+
+impl jack::ProcessHandler for JackProcessor {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        let channels = self.ports.len();
+        let n = (ps.n_frames() as usize).min(self.buf.len() / channels);
+        for (c, port) in self.ports.iter().enumerate() {
+            for (i, v) in port.as_slice(ps).iter().take(n).enumerate() {
+                self.buf[i * channels + c] = *v;
+            }
+        }
+        for frame in self.buf[..n * channels].chunks(channels) {
+            let sample = self.channel_selection.mono_sample(channels, |c| frame[c]);
+            if self.samples.push(sample).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        Control::Continue
+    }
+
+    fn buffer_size(&mut self, _: &Client, size: Frames) -> Control {
+        self.buf = vec![0.; size as usize * self.ports.len()];
+        Control::Continue
+    }
+}
+
+/// Takes the samples out of the queue in blocks of up to `block`
+/// samples and passes them on, until stopped.
+fn start_reader_thread(
+    mut samples: Consumer<f32>,
+    block: usize,
+    dropped: Arc<AtomicUsize>,
+    mut vals_handler: Box<dyn ValsHandler>,
+) -> StoppableHandle<()> {
+    spawn(move |stopped| {
+        let mut buf = Vec::with_capacity(block);
+        while !stopped.get() {
+            thread::sleep(READ_INTERVAL);
+            while let Ok(sample) = samples.pop() {
+                buf.push(sample);
+                if buf.len() == block {
+                    vals_handler.take_frame(&buf);
+                    buf.clear();
+                }
+            }
+            if !buf.is_empty() {
+                vals_handler.take_frame(&buf);
+                buf.clear();
+            }
+            let n = dropped.swap(0, Ordering::Relaxed);
+            if n > 0 {
+                warn!("JACK audio queue full, dropped {} samples.", n);
+            }
+        }
+    })
+}
+
+/// Counts and reports xruns, and notices when the server goes away.
+pub struct JackNotifications {
+    xruns: Arc<AtomicUsize>,
+    failed: Arc<AtomicBool>,
+}
+
+impl jack::NotificationHandler for JackNotifications {
+    fn xrun(&mut self, _: &Client) -> Control {
+        let n = self.xruns.fetch_add(1, Ordering::SeqCst) + 1;
+        warn!("JACK xrun, {} so far.", n);
+        Control::Continue
+    }
+
+    fn shutdown(&mut self, _: jack::ClientStatus, reason: &str) {
+        warn!("JACK server shut down: {}", reason);
+        self.failed.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Reads audio from JACK.  An input port is registered for every
+/// configured source port, and connected to it once processing
+/// starts.  Each port is a channel for the ChannelSelection.
+pub struct JackAudioSource {
+    client: Option<Client>,
+    ports: Vec<Port<AudioIn>>,
+    source_ports: Vec<String>,
+    sample_rate: f32,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    xruns: Arc<AtomicUsize>,
+    failed: Arc<AtomicBool>,
+    active_client: Option<AsyncClient<JackNotifications, JackProcessor>>,
+    reader: Option<StoppableHandle<()>>,
+}
+
+impl JackAudioSource {
+    /// Connects to the JACK server.  `source_ports` are full port names
+    /// like `system:capture_1`.  If none are given, a single unconnected
+    /// port is registered, to be connected by hand.
+    pub fn new(source_ports: Vec<String>) -> JackAudioSource {
+        JackAudioSource::try_new(source_ports).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like new, but returns an error if no JACK server is running.
+    pub fn try_new(source_ports: Vec<String>) -> Result<JackAudioSource, String> {
+        let (client, status) = Client::new(CLIENT_NAME, ClientOptions::NO_START_SERVER)
+            .map_err(|e| format!("Failed to connect to JACK server: {}", e))?;
+        info!("Connected to JACK as {} ({:?}).", client.name(), status);
+        let n_ports = source_ports.len().max(1);
+        let ports = (0..n_ports)
+            .map(|i| {
+                client
+                    .register_port(&format!("in_{}", i + 1), AudioIn)
+                    .map_err(|e| format!("Failed to register JACK port: {}", e))
+            })
+            .collect::<Result<_, _>>()?;
+        let sample_rate = client.sample_rate() as f32;
+        Ok(JackAudioSource {
+            client: Some(client),
+            ports,
+            source_ports,
+            sample_rate,
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            xruns: Arc::new(AtomicUsize::new(0)),
+            failed: Arc::new(AtomicBool::new(false)),
+            active_client: None,
+            reader: None,
+        })
+    }
+
+    /// The number of xruns since the source was created.
+    pub fn get_xrun_count(&self) -> usize {
+        self.xruns.load(Ordering::SeqCst)
+    }
+}
+
+impl AudioSource for JackAudioSource {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        let client = self.client.take().expect("JACK client is already active");
+        let vals_handler = resample_if_needed(self.sample_rate, self.get_sample_rate(), vals_handler);
+        let our_ports: Vec<String> = self
+            .ports
+            .iter()
+            .map(|p| p.name().expect("Failed to get JACK port name"))
+            .collect();
+        let block = client.buffer_size() as usize;
+        let (producer, consumer) = RingBuffer::new((self.sample_rate * QUEUE_SECS) as usize);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let processor = JackProcessor {
+            buf: vec![0.; block * self.ports.len()],
+            ports: self.ports.drain(..).collect(),
+            channel_selection: self.channel_selection,
+            samples: producer,
+            dropped: Arc::clone(&dropped),
+        };
+        let notifications = JackNotifications {
+            xruns: Arc::clone(&self.xruns),
+            failed: Arc::clone(&self.failed),
+        };
+        let active_client = match client.activate_async(notifications, processor) {
+            Ok(active_client) => active_client,
+            Err(e) => {
+                warn!("Failed to activate JACK client: {}", e);
+                self.failed.store(true, Ordering::SeqCst);
+                return;
+            }
+        };
+        // ports can only be connected once the client is active
+        for (source, ours) in self.source_ports.iter().zip(our_ports.iter()) {
+            match active_client.as_client().connect_ports_by_name(source, ours) {
+                Ok(()) => info!("Connected {} to {}.", source, ours),
+                Err(e) => warn!("Failed to connect {} to {}: {}", source, ours, e),
+            }
+        }
+        self.active_client = Some(active_client);
+        self.reader = Some(start_reader_thread(consumer, block, dropped, vals_handler));
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        if let Some(active_client) = self.active_client.take() {
+            // keep the client and ports around, so processing can be
+            // started again
+            match active_client.deactivate() {
+                Ok((client, _, processor)) => {
+                    self.client = Some(client);
+                    self.ports = processor.ports;
+                }
+                Err(e) => warn!("Failed to deactivate JACK client: {}", e),
+            }
+        }
+        if let Some(reader) = self.reader.take() {
+            reader.stop().join().unwrap();
+        }
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+/// Lists the JACK ports that audio can be read from, as single
+/// channel devices.  Empty if no JACK server is running.
+pub fn enumerate_jack_ports() -> Vec<DeviceInfo> {
+    let client = match Client::new(CLIENT_NAME, ClientOptions::NO_START_SERVER) {
+        Ok((client, _)) => client,
+        Err(_) => return vec![],
+    };
+    let sample_rate = client.sample_rate() as u32;
+    client
+        .ports(None, Some("32 bit float mono audio"), PortFlags::IS_OUTPUT)
+        .into_iter()
+        .map(|port| DeviceInfo {
+            spec: format!("jack:{}", port),
+            name: port,
+            host: "JACK".to_string(),
+            is_default: false,
+            default_config: Some(ConfigInfo {
+                channels: 1,
+                sample_rate,
+                sample_format: "f32".to_string(),
+            }),
+            supported_configs: vec![ConfigRange {
+                channels: 1,
+                min_sample_rate: sample_rate,
+                max_sample_rate: sample_rate,
+                sample_format: "f32".to_string(),
+            }],
+        })
+        .collect()
+}
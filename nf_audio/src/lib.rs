@@ -1,9 +1,30 @@
-//! This module takes care of audio interfacing using cpal.
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+//! This module takes care of audio interfacing.  Audio can come from
+//! a cpal device, JACK, an audio file, raw PCM on stdin or the
+//! network.  All of them implement the AudioSource trait and deliver
+//! mono frames to a ValsHandler.
+mod channels;
+mod cpal_getter;
+mod devices;
+mod fallback;
+mod file_source;
+#[cfg(feature = "jack")]
+mod jack_source;
+mod network;
+mod recorder;
+mod resampler;
+mod stdin_source;
+pub use channels::ChannelSelection;
+pub use cpal_getter::CpalAudioGetter;
+pub use devices::{enumerate_devices, list_devices, ConfigInfo, ConfigRange, DeviceInfo};
+pub use fallback::{FallbackSource, FRAME_TIMEOUT};
+pub use file_source::FileAudioSource;
+#[cfg(feature = "jack")]
+pub use jack_source::JackAudioSource;
+pub use network::{NetworkAudioSource, NetworkSender};
+pub use recorder::Recorder;
+pub use resampler::{Resampler, ResamplingHandler};
+pub use stdin_source::{PcmFormat, StdinAudioSource};
 use cpal::platform::Host;
-use cpal::{SupportedStreamConfig, SampleFormat, StreamConfig, BufferSize};
-use log::{info, debug};
-use stoppable_thread::spawn;
 
 #[cfg(target_os = "windows")]
 fn get_host() -> Host {
@@ -15,118 +36,157 @@ fn get_host() -> Host {
     cpal::default_host()
 }
 
-pub fn list_devices() {
-    let host = get_host();
-    for input_dev in host.input_devices().expect("Failed to get input devices") {
-        let name = input_dev.name().unwrap_or("NO NAME".to_string());
-        println!("Device: {:?}", name);
-    }
-}
-
 /// A handler that periodically receives audio frames.
 pub trait ValsHandler: Send + Sync {
     fn take_frame(&mut self, frame: &[f32]);
+
+    /// Called when audio input is lost or comes back.  Only sources
+    /// that can recover, like the FallbackSource, report this.
+    fn status_changed(&mut self, _status: &SourceStatus) {}
 }
 
-pub struct CpalAudioGetter {
-    dev: cpal::Device,
-    config: SupportedStreamConfig,
-    stream: Option<cpal::Stream>,
+/// Whether audio is currently coming in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceStatus {
+    /// Audio is read from the source with the given spec.
+    Active(String),
+    /// No source could be opened, or the last one failed.
+    NoAudio,
 }
 
-impl CpalAudioGetter {
-    pub fn new(dev_name: String) -> CpalAudioGetter {
-        let host = get_host();
-        // Setup the input device and stream with the default input config.
-        let device = if dev_name == "default" {
-            host.default_input_device()
-        } else {
-            host.input_devices().expect("Failed to scan input devices")
-                .find(|x| x.name().map(|y| y == dev_name).unwrap_or(false))
-        }
-        .expect("failed to find input device");
-        info!("Selected input device: {}", device.name().unwrap());
-        let config = device.default_input_config().expect("Failed to get default input config");
-        info!("Selected config: {:?}", config);
-        CpalAudioGetter {
-            dev: device,
-            config: config,
-            stream: None,
-        }
+/// Something that produces audio and hands it to a ValsHandler in
+/// frames.  The frames are mono and normalized to [-1, 1].
+pub trait AudioSource {
+    /// Selects which channel is processed, or whether all channels
+    /// are mixed down.  Takes effect on the next start_processing.
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection);
+
+    /// If a target sample rate is set, the audio is resampled to it
+    /// before it is handed to the ValsHandler.  This way processing
+    /// parameters behave the same on 44.1kHz and 48kHz devices.
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>);
+
+    /// The sample rate of the frames that the ValsHandler receives.
+    fn get_sample_rate(&self) -> f32;
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>);
+
+    fn stop_processing(&mut self);
+
+    /// Whether the source has run out of audio, i.e. the end of a
+    /// file was reached.  Devices never finish.
+    fn is_finished(&self) -> bool {
+        false
     }
 
-    pub fn get_sample_rate(&self) -> f32 {
-        println!("{}", self.config.channels());
-        self.config.sample_rate().0 as f32
+    /// Whether the stream broke, i.e. the device was unplugged or the
+    /// JACK server went away.  The source has to be reopened then.
+    fn has_failed(&self) -> bool {
+        false
     }
+}
+
+/// Opens an audio source from a specification string:
+///
+/// - `file:track.wav` plays a WAV or FLAC file in real time.
+/// - `file-fast:track.flac` plays a file as fast as possible.
+/// - `stdin:44100:2:s16le` reads raw PCM from stdin, with the sample
+///   rate, channel count and sample format.  These can be left out
+///   from the right, the defaults are CD format (`44100:2:s16le`).
+/// - `jack:system:capture_1` reads from JACK and connects to the given
+///   port.  Several ports are separated by commas and become channels.
+///   Requires the `jack` feature.
+/// - `udp:5005` or `udp:192.168.1.10:5005` receives audio sent by
+///   `nf_send` on that port.  The source fails when no packets arrive
+///   for 10 seconds, another timeout can be appended, like
+///   `udp:5005:30`, or `udp:5005:off` to wait forever.
+/// - `cpal:name` or just `name` opens the cpal input device with that
+///   name.  `default` is the default input device.  A name with a
+///   colon that is no cpal device is taken as a JACK port, as in older
+///   configs.
+pub fn open_source(spec: &str) -> Box<dyn AudioSource> {
+    try_open_source(spec).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Like open_source, but returns an error instead of panicking if a
+/// device is not available.  Invalid specs still panic.
+pub fn try_open_source(spec: &str) -> Result<Box<dyn AudioSource>, String> {
+    let (kind, arg) = match spec.find(':') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => (spec, ""),
+    };
+    Ok(match kind {
+        "file" => Box::new(FileAudioSource::new(arg, true)),
+        "file-fast" => Box::new(FileAudioSource::new(arg, false)),
+        "stdin" => {
+            let mut parts = arg.split(':').filter(|p| !p.is_empty());
+            let sample_rate = parts
+                .next()
+                .map(|p| p.parse().expect("Invalid sample rate"))
+                .unwrap_or(44100.);
+            let channels = parts
+                .next()
+                .map(|p| p.parse().expect("Invalid channel count"))
+                .unwrap_or(2);
+            let format = parts
+                .next()
+                .map(|p| p.parse().unwrap())
+                .unwrap_or(PcmFormat::S16LE);
+            Box::new(StdinAudioSource::new(sample_rate, channels, format))
+        }
+        #[cfg(feature = "jack")]
+        "jack" => Box::new(JackAudioSource::try_new(
+            arg.split(',')
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string())
+                .collect(),
+        )?),
+        #[cfg(not(feature = "jack"))]
+        "jack" => panic!("nf_audio was built without JACK support"),
+        "udp" => Box::new(NetworkAudioSource::try_from_spec(arg)?),
+        "cpal" => Box::new(CpalAudioGetter::try_new(arg.to_string())?),
+        _ => match CpalAudioGetter::try_new(spec.to_string()) {
+            Ok(getter) => Box::new(getter),
+            // older configs named JACK ports without the prefix
+            Err(e) if spec.contains(':') => open_unprefixed_jack_port(spec, e)?,
+            Err(e) => return Err(e),
+        },
+    })
+}
 
-    pub fn start_processing(&mut self, mut vals_handler: Box<dyn ValsHandler>) {
-        let err_fn = move |err| {
-            eprintln!("an error occurred on stream: {}", err);
-        };
-        debug!("In start_processing.");
-        let stream = match self.config.sample_format() {
-            cpal::SampleFormat::F32 => {
-                info!("Building input stream with F32 sample_format.");
-                self.dev.build_input_stream(
-                    &StreamConfig {
-                        channels: 2,
-                        sample_rate: self.config.sample_rate(),
-                        buffer_size: BufferSize::Default,
-                    },
-                    //&self.config.clone().into(),
-                    move |data, _: &_| {
-                        debug!("Received {:?} samples.", data.len());
-                        let b_new: Vec<f32> = data.chunks(2).map(|c| c[0]).collect();
-                        vals_handler.take_frame(b_new.as_slice());
-                    },
-                    err_fn,
-                ).expect("Failed to open stream")
-            },
-            cpal::SampleFormat::I16 => {
-                info!("Building input stream with I16 sample_format.");
-                self.dev.build_input_stream(
-                    &StreamConfig {
-                        channels: 2,
-                        sample_rate: self.config.sample_rate(),
-                        buffer_size: BufferSize::Default,
-                    },
-                    //&self.config.clone().into(),
-                    move |data, _: &_| {
-                        debug!("Received {:?} samples.", data.len());
-                        let b_new: Vec<i16> = data.chunks(2).map(|c| c[0]).collect();
-                        let b_new: Vec<f32> = b_new.into_iter().map(|v| v as f32).collect();
-                        vals_handler.take_frame(b_new.as_slice());
-                    },
-                    err_fn,
-                ).expect("Failed to open stream")
-            },
-            cpal::SampleFormat::U16 => {
-                info!("Building input stream with U16 sample_format.");
-                self.dev.build_input_stream(
-                    &StreamConfig {
-                        channels: 2,
-                        sample_rate: self.config.sample_rate(),
-                        buffer_size: BufferSize::Default,
-                    },
-                    //&self.config.clone().into(),
-                    move |data, _: &_| {
-                        debug!("Received {:?} samples.", data.len());
-                        let b_new: Vec<u16> = data.chunks(2).map(|c| c[0]).collect();
-                        let b_new: Vec<f32> = b_new.into_iter().map(|v| v as f32).collect();
-                        vals_handler.take_frame(b_new.as_slice());
-                    },
-                    err_fn,
-                ).expect("Failed to open stream")
-            },
-        };
-        info!("Starting stream.");
-        stream.play().expect("Failed to start stream");
-        self.stream = Some(stream);
+/// Opens a spec like `system:capture_1` that is no cpal device as the
+/// JACK port it used to mean.
+#[cfg(feature = "jack")]
+fn open_unprefixed_jack_port(spec: &str, cpal_error: String) -> Result<Box<dyn AudioSource>, String> {
+    log::warn!(
+        "No audio device named {}, trying it as a JACK port.  Write jack:{} for that.",
+        spec,
+        spec
+    );
+    match JackAudioSource::try_new(vec![spec.to_string()]) {
+        Ok(source) => Ok(Box::new(source)),
+        Err(e) => Err(format!("{}; as a JACK port: {}", cpal_error, e)),
     }
+}
+
+#[cfg(not(feature = "jack"))]
+fn open_unprefixed_jack_port(spec: &str, cpal_error: String) -> Result<Box<dyn AudioSource>, String> {
+    Err(format!(
+        "{}.  For a JACK port use jack:{} with the jack feature.",
+        cpal_error, spec
+    ))
+}
 
-    pub fn stop_processing(&mut self) {
-        info!("Stopping processing.");
-        let stream = std::mem::replace(&mut self.stream, None).unwrap();
+/// Wraps the handler in a ResamplingHandler, if the rates differ.
+fn resample_if_needed(
+    from_rate: f32,
+    to_rate: f32,
+    vals_handler: Box<dyn ValsHandler>,
+) -> Box<dyn ValsHandler> {
+    if from_rate != to_rate {
+        log::info!("Resampling from {} Hz to {} Hz.", from_rate, to_rate);
+        Box::new(ResamplingHandler::new(from_rate, to_rate, vals_handler))
+    } else {
+        vals_handler
     }
 }
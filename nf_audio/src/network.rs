@@ -0,0 +1,591 @@
+//! Audio over UDP, so the analysis can run next to the lights while
+//! the audio is captured somewhere else.
+//!
+//! Every packet has a 20 byte header, all numbers little endian:
+//!
+//! | bytes | content                                   |
+//! |-------|-------------------------------------------|
+//! | 0-3   | magic `NFA1`                              |
+//! | 4-7   | stream id, u32, random for every sender   |
+//! | 8-11  | sequence number, u32, counting up from 0  |
+//! | 12-15 | sample rate, u32                          |
+//! | 16-17 | channels, u16                             |
+//! | 18-19 | frames in this packet, u16                |
+//!
+//! followed by `frames * channels` interleaved s16 samples.
+//!
+//! A new stream id means the sender restarted, the receiver starts
+//! over with its sequence numbers then.  Within a stream, the receiver
+//! puts packets that arrive out of order back in order.
+//! A missing packet is waited for until `JITTER_PACKETS` later
+//! packets have arrived, then it is counted as lost and replaced by
+//! the previous packet, faded out.
+use crate::{resample_if_needed, AudioSource, ChannelSelection, ValsHandler};
+use cpal::Sample;
+use log::{debug, info, warn};
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use stoppable_thread::{spawn, StoppableHandle};
+
+const MAGIC: &[u8; 4] = b"NFA1";
+const HEADER_SIZE: usize = 20;
+/// Frames per packet, small enough to stay below the usual MTU.
+pub const MAX_PACKET_FRAMES: usize = 512;
+/// How many packets may arrive before a missing one is given up.
+const JITTER_PACKETS: usize = 4;
+/// How long to wait for a missing packet if nothing else arrives.
+const JITTER_TIMEOUT: Duration = Duration::from_millis(100);
+/// How long to wait for the first packet when opening the source.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Without packets for this long the source has failed, unless the
+/// spec gives another timeout.
+pub const PACKET_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sequence numbers this far ahead are taken as a gap in the stream,
+/// which continues from there.  Packets further behind are dropped.
+const MAX_SEQ_JUMP: u32 = 1000;
+
+/// The header of a packet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PacketHeader {
+    pub stream: u32,
+    pub seq: u32,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: u16,
+}
+
+/// Encodes a packet with the given samples, which are interleaved
+/// and in [-1, 1].
+pub fn encode_packet(header: &PacketHeader, samples: &[f32]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(HEADER_SIZE + samples.len() * 2);
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&header.stream.to_le_bytes());
+    buf.extend_from_slice(&header.seq.to_le_bytes());
+    buf.extend_from_slice(&header.sample_rate.to_le_bytes());
+    buf.extend_from_slice(&header.channels.to_le_bytes());
+    buf.extend_from_slice(&header.frames.to_le_bytes());
+    for sample in samples {
+        buf.extend_from_slice(&sample.clamp(-1., 1.).to_i16().to_le_bytes());
+    }
+    buf
+}
+
+/// Decodes a packet, None if it is not a valid packet.
+pub fn decode_packet(buf: &[u8]) -> Option<(PacketHeader, Vec<f32>)> {
+    if buf.len() < HEADER_SIZE || &buf[0..4] != MAGIC {
+        return None;
+    }
+    let u32_at = |i: usize| u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]]);
+    let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+    let header = PacketHeader {
+        stream: u32_at(4),
+        seq: u32_at(8),
+        sample_rate: u32_at(12),
+        channels: u16_at(16),
+        frames: u16_at(18),
+    };
+    let n = header.frames as usize * header.channels as usize;
+    if header.channels == 0 || buf.len() < HEADER_SIZE + n * 2 {
+        return None;
+    }
+    let samples = buf[HEADER_SIZE..HEADER_SIZE + n * 2]
+        .chunks(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]).to_f32())
+        .collect();
+    Some((header, samples))
+}
+
+/// Puts packets back in order and fills in lost ones.
+struct JitterBuffer {
+    packets: BTreeMap<u32, Vec<f32>>,
+    stream: Option<u32>,
+    next_seq: Option<u32>,
+    t_last_delivery: Instant,
+    /// The last delivered packet, repeated to conceal losses.
+    last: Vec<f32>,
+    lost_in_a_row: u32,
+    lost_total: u64,
+}
+
+impl JitterBuffer {
+    fn new() -> JitterBuffer {
+        JitterBuffer {
+            packets: BTreeMap::new(),
+            stream: None,
+            next_seq: None,
+            t_last_delivery: Instant::now(),
+            last: vec![],
+            lost_in_a_row: 0,
+            lost_total: 0,
+        }
+    }
+
+    fn push(&mut self, stream: u32, seq: u32, samples: Vec<f32>) {
+        if self.stream != Some(stream) {
+            if self.stream.is_some() {
+                info!("Network audio stream restarted.");
+            }
+            self.stream = Some(stream);
+            self.packets.clear();
+            self.next_seq = None;
+        }
+        let next_seq = *self.next_seq.get_or_insert(seq);
+        if seq.wrapping_sub(next_seq) > MAX_SEQ_JUMP && next_seq.wrapping_sub(seq) > MAX_SEQ_JUMP {
+            info!("Network audio stream skipped to packet {}.", seq);
+            self.packets.clear();
+            self.next_seq = Some(seq);
+        } else if seq.wrapping_sub(next_seq) > MAX_SEQ_JUMP {
+            debug!("Dropping late packet {}.", seq);
+            return;
+        }
+        self.packets.insert(seq, samples);
+    }
+
+    /// Returns the packets that are ready, in order.  Missing packets
+    /// are concealed once enough later packets arrived, or after a
+    /// timeout.
+    fn pop_ready(&mut self) -> Vec<Vec<f32>> {
+        let mut res = vec![];
+        while let Some(next_seq) = self.next_seq {
+            if let Some(samples) = self.packets.remove(&next_seq) {
+                self.lost_in_a_row = 0;
+                self.last = samples.clone();
+                res.push(samples);
+            } else if !self.packets.is_empty()
+                && (self.packets.len() >= JITTER_PACKETS
+                    || self.t_last_delivery.elapsed() > JITTER_TIMEOUT)
+            {
+                debug!("Packet {} lost.", next_seq);
+                self.lost_in_a_row += 1;
+                self.lost_total += 1;
+                res.push(self.conceal());
+            } else {
+                break;
+            }
+            self.next_seq = Some(next_seq.wrapping_add(1));
+            self.t_last_delivery = Instant::now();
+        }
+        res
+    }
+
+    /// The last packet again, quieter for every packet lost in a row.
+    fn conceal(&self) -> Vec<f32> {
+        let gain = 0.5f32.powi(self.lost_in_a_row as i32);
+        self.last.iter().map(|x| x * gain).collect()
+    }
+}
+
+/// Reads the part of a `udp:` spec after the colon: a port, or an
+/// address with a port, optionally followed by the packet timeout in
+/// seconds or `off`.
+fn parse_spec(arg: &str) -> (String, Option<Duration>) {
+    let parts: Vec<&str> = arg.split(':').collect();
+    let (addr, timeout) = match parts.as_slice() {
+        [port] => (vec![*port], None),
+        [host, port] if host.parse::<u16>().is_err() => (vec![*host, *port], None),
+        [addr @ .., timeout] => (addr.to_vec(), Some(*timeout)),
+        [] => unreachable!(),
+    };
+    let addr = match addr.as_slice() {
+        [port] => format!("0.0.0.0:{}", port),
+        _ => addr.join(":"),
+    };
+    let timeout = match timeout {
+        None => Some(PACKET_TIMEOUT),
+        Some("off") => None,
+        Some(secs) => match secs.parse::<f32>() {
+            Ok(secs) if secs > 0. => Some(Duration::from_secs_f32(secs)),
+            _ => panic!("Invalid packet timeout: {}", secs),
+        },
+    };
+    (addr, timeout)
+}
+
+/// Receives audio sent with `NetworkSender`, e.g. by `nf_send`.
+pub struct NetworkAudioSource {
+    socket: Option<UdpSocket>,
+    sample_rate: f32,
+    channels: usize,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    packet_timeout: Option<Duration>,
+    failed: Arc<AtomicBool>,
+    thread: Option<StoppableHandle<()>>,
+}
+
+impl NetworkAudioSource {
+    /// Opens the source for a `udp:` spec, see `open_source`.
+    pub fn try_from_spec(arg: &str) -> Result<NetworkAudioSource, String> {
+        let (addr, packet_timeout) = parse_spec(arg);
+        NetworkAudioSource::try_new(&addr, packet_timeout)
+    }
+
+    /// Listens on the given address, like `0.0.0.0:5005`, and waits
+    /// shortly for the first packet, to learn the sample rate and
+    /// channel count.  If no packets arrive for `packet_timeout`
+    /// later on, the source has failed.
+    pub fn try_new(
+        addr: &str,
+        packet_timeout: Option<Duration>,
+    ) -> Result<NetworkAudioSource, String> {
+        let socket =
+            UdpSocket::bind(addr).map_err(|e| format!("Failed to bind {}: {}", addr, e))?;
+        socket.set_read_timeout(Some(CONNECT_TIMEOUT)).unwrap();
+        let mut buf = [0u8; 65536];
+        let t_start = Instant::now();
+        let header = loop {
+            let size = socket
+                .recv(&mut buf)
+                .map_err(|_| format!("No audio received on {}", addr))?;
+            if let Some((header, _)) = decode_packet(&buf[..size]) {
+                break header;
+            }
+            if t_start.elapsed() > CONNECT_TIMEOUT {
+                return Err(format!("No valid audio packets received on {}", addr));
+            }
+        };
+        info!(
+            "Receiving audio on {} ({} Hz, {} channels).",
+            addr, header.sample_rate, header.channels
+        );
+        Ok(NetworkAudioSource {
+            socket: Some(socket),
+            sample_rate: header.sample_rate as f32,
+            channels: header.channels as usize,
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            packet_timeout,
+            failed: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        })
+    }
+}
+
+impl AudioSource for NetworkAudioSource {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        let socket = self
+            .socket
+            .take()
+            .expect("Network source is already running");
+        socket.set_read_timeout(Some(JITTER_TIMEOUT / 2)).unwrap();
+        let mut vals_handler =
+            resample_if_needed(self.sample_rate, self.get_sample_rate(), vals_handler);
+        let sample_rate = self.sample_rate as u32;
+        let channels = self.channels;
+        let channel_selection = self.channel_selection;
+        let packet_timeout = self.packet_timeout;
+        let failed = Arc::clone(&self.failed);
+        self.thread = Some(spawn(move |stopped| {
+            let mut jitter_buffer = JitterBuffer::new();
+            let mut buf = [0u8; 65536];
+            let mut lost_reported = 0;
+            let mut t_last_packet = Instant::now();
+            while !stopped.get() {
+                if let Ok(size) = socket.recv(&mut buf) {
+                    match decode_packet(&buf[..size]) {
+                        Some((header, samples))
+                            if header.sample_rate == sample_rate
+                                && header.channels as usize == channels =>
+                        {
+                            t_last_packet = Instant::now();
+                            jitter_buffer.push(header.stream, header.seq, samples)
+                        }
+                        Some(_) => warn!("Audio format of the sender changed, ignoring packet."),
+                        None => debug!("Received invalid packet."),
+                    }
+                }
+                for samples in jitter_buffer.pop_ready() {
+                    vals_handler.take_frame(&channel_selection.to_mono(&samples, channels));
+                }
+                if jitter_buffer.lost_total >= lost_reported + 100 {
+                    lost_reported = jitter_buffer.lost_total;
+                    warn!("{} network audio packets lost so far.", lost_reported);
+                }
+                if let Some(timeout) = packet_timeout {
+                    if t_last_packet.elapsed() > timeout && !failed.load(Ordering::SeqCst) {
+                        warn!("No audio packets received for {:?}.", timeout);
+                        failed.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+        }));
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        if let Some(thread) = self.thread.take() {
+            thread.stop().join().unwrap();
+        }
+    }
+
+    fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+}
+
+/// Sends the frames it receives over UDP, in the format described
+/// above.  The frames are mono.
+pub struct NetworkSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+    sample_rate: u32,
+    stream: u32,
+    seq: u32,
+}
+
+impl NetworkSender {
+    pub fn new(target: &str, sample_rate: f32) -> NetworkSender {
+        let target = target
+            .to_socket_addrs()
+            .expect("Invalid target address")
+            .next()
+            .expect("Target address not found");
+        let socket = UdpSocket::bind("0.0.0.0:0").expect("Failed to open UDP socket");
+        info!("Sending audio to {}.", target);
+        // differs between runs, so the receiver notices a restart
+        let t_now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        NetworkSender {
+            socket,
+            target,
+            sample_rate: sample_rate as u32,
+            stream: t_now.subsec_nanos() ^ process::id(),
+            seq: 0,
+        }
+    }
+}
+
+impl ValsHandler for NetworkSender {
+    fn take_frame(&mut self, frame: &[f32]) {
+        for chunk in frame.chunks(MAX_PACKET_FRAMES) {
+            let header = PacketHeader {
+                stream: self.stream,
+                seq: self.seq,
+                sample_rate: self.sample_rate,
+                channels: 1,
+                frames: chunk.len() as u16,
+            };
+            if let Err(e) = self
+                .socket
+                .send_to(&encode_packet(&header, chunk), self.target)
+            {
+                debug!("Failed to send packet: {}", e);
+            }
+            self.seq = self.seq.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+
+    fn packet(value: f32) -> Vec<f32> {
+        vec![value; 4]
+    }
+
+    /// The first sample of every packet that is ready.
+    fn pop_values(jitter_buffer: &mut JitterBuffer) -> Vec<f32> {
+        jitter_buffer.pop_ready().iter().map(|p| p[0]).collect()
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let header = PacketHeader {
+            stream: 0xdead_beef,
+            seq: 7,
+            sample_rate: 48000,
+            channels: 2,
+            frames: 3,
+        };
+        let samples = [-1., -0.5, 0., 0.25, 0.75, 1.];
+        let (decoded, decoded_samples) = decode_packet(&encode_packet(&header, &samples)).unwrap();
+        assert_eq!(decoded, header);
+        for (a, b) in samples.iter().zip(&decoded_samples) {
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn invalid_packets_are_rejected() {
+        let header = PacketHeader {
+            stream: 1,
+            seq: 0,
+            sample_rate: 48000,
+            channels: 1,
+            frames: 4,
+        };
+        let buf = encode_packet(&header, &[0.; 4]);
+        assert!(decode_packet(&buf[..buf.len() - 1]).is_none());
+        assert!(decode_packet(&buf[..HEADER_SIZE - 1]).is_none());
+        let mut wrong_magic = buf.clone();
+        wrong_magic[0] = b'X';
+        assert!(decode_packet(&wrong_magic).is_none());
+        let no_channels = PacketHeader {
+            channels: 0,
+            ..header
+        };
+        assert!(decode_packet(&encode_packet(&no_channels, &[])).is_none());
+    }
+
+    #[test]
+    fn packets_are_put_back_in_order() {
+        let mut jitter_buffer = JitterBuffer::new();
+        jitter_buffer.push(1, 0, packet(0.));
+        assert_eq!(pop_values(&mut jitter_buffer), [0.]);
+        jitter_buffer.push(1, 2, packet(2.));
+        assert!(pop_values(&mut jitter_buffer).is_empty());
+        jitter_buffer.push(1, 1, packet(1.));
+        assert_eq!(pop_values(&mut jitter_buffer), [1., 2.]);
+        assert_eq!(jitter_buffer.lost_total, 0);
+    }
+
+    #[test]
+    fn late_packets_are_dropped() {
+        let mut jitter_buffer = JitterBuffer::new();
+        jitter_buffer.push(1, 10, packet(10.));
+        jitter_buffer.push(1, 11, packet(11.));
+        assert_eq!(pop_values(&mut jitter_buffer), [10., 11.]);
+        jitter_buffer.push(1, 9, packet(9.));
+        assert!(pop_values(&mut jitter_buffer).is_empty());
+    }
+
+    #[test]
+    fn lost_packets_are_concealed() {
+        let mut jitter_buffer = JitterBuffer::new();
+        jitter_buffer.push(1, 0, packet(1.));
+        assert_eq!(pop_values(&mut jitter_buffer), [1.]);
+        for seq in 3..2 + JITTER_PACKETS as u32 {
+            jitter_buffer.push(1, seq, packet(seq as f32));
+            assert!(pop_values(&mut jitter_buffer).is_empty());
+        }
+        // enough later packets arrived, 1 and 2 are given up
+        jitter_buffer.push(1, 2 + JITTER_PACKETS as u32, packet(6.));
+        assert_eq!(pop_values(&mut jitter_buffer), [0.5, 0.25, 3., 4., 5., 6.]);
+        assert_eq!(jitter_buffer.lost_total, 2);
+    }
+
+    #[test]
+    fn lost_packets_are_concealed_after_a_timeout() {
+        let mut jitter_buffer = JitterBuffer::new();
+        jitter_buffer.push(1, 0, packet(1.));
+        assert_eq!(pop_values(&mut jitter_buffer), [1.]);
+        jitter_buffer.push(1, 2, packet(2.));
+        assert!(pop_values(&mut jitter_buffer).is_empty());
+        thread::sleep(JITTER_TIMEOUT + Duration::from_millis(20));
+        assert_eq!(pop_values(&mut jitter_buffer), [0.5, 2.]);
+    }
+
+    #[test]
+    fn restarted_sender_is_followed() {
+        let mut jitter_buffer = JitterBuffer::new();
+        for seq in 0..500 {
+            jitter_buffer.push(1, seq, packet(1.));
+        }
+        assert_eq!(jitter_buffer.pop_ready().len(), 500);
+        // counts from 0 again, with another stream id
+        jitter_buffer.push(2, 0, packet(2.));
+        jitter_buffer.push(2, 1, packet(3.));
+        assert_eq!(pop_values(&mut jitter_buffer), [2., 3.]);
+    }
+
+    #[test]
+    fn gap_in_the_stream_is_skipped() {
+        let mut jitter_buffer = JitterBuffer::new();
+        jitter_buffer.push(1, 0, packet(0.));
+        assert_eq!(pop_values(&mut jitter_buffer), [0.]);
+        jitter_buffer.push(1, 2 * MAX_SEQ_JUMP, packet(1.));
+        assert_eq!(pop_values(&mut jitter_buffer), [1.]);
+        assert_eq!(jitter_buffer.lost_total, 0);
+    }
+
+    #[test]
+    fn specs() {
+        let (addr, timeout) = parse_spec("5005");
+        assert_eq!(addr, "0.0.0.0:5005");
+        assert_eq!(timeout, Some(PACKET_TIMEOUT));
+        assert_eq!(parse_spec("localhost:5005").0, "localhost:5005");
+        assert_eq!(
+            parse_spec("5005:2.5"),
+            (
+                "0.0.0.0:5005".to_string(),
+                Some(Duration::from_millis(2500))
+            )
+        );
+        assert_eq!(
+            parse_spec("192.168.1.10:5005:off"),
+            ("192.168.1.10:5005".to_string(), None)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid packet timeout")]
+    fn zero_timeout_is_rejected() {
+        parse_spec("5005:0");
+    }
+
+    struct Collector(Arc<Mutex<Vec<f32>>>);
+
+    impl ValsHandler for Collector {
+        fn take_frame(&mut self, frame: &[f32]) {
+            self.0.lock().unwrap().extend_from_slice(frame);
+        }
+    }
+
+    #[test]
+    fn loopback() {
+        // a free port, for a moment at least
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{}", port);
+        let sender = {
+            let addr = addr.clone();
+            thread::spawn(move || {
+                let mut sender = NetworkSender::new(&addr, 8000.);
+                for _ in 0..100 {
+                    sender.take_frame(&[0.5; 64]);
+                    thread::sleep(Duration::from_millis(5));
+                }
+            })
+        };
+        let mut source =
+            NetworkAudioSource::try_new(&addr, Some(Duration::from_millis(300))).unwrap();
+        assert_eq!(source.get_sample_rate(), 8000.);
+        let received = Arc::new(Mutex::new(Vec::new()));
+        source.start_processing(Box::new(Collector(Arc::clone(&received))));
+        sender.join().unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!source.has_failed());
+        {
+            let received = received.lock().unwrap();
+            assert!(!received.is_empty());
+            assert!(received.iter().all(|x| (x - 0.5).abs() < 1e-4));
+        }
+        // the sender is gone
+        thread::sleep(Duration::from_millis(400));
+        assert!(source.has_failed());
+        source.stop_processing();
+    }
+}
@@ -0,0 +1,194 @@
+use crate::{SourceStatus, ValsHandler};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::{info, warn};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+struct RecorderState {
+    buffer: VecDeque<f32>,
+    capacity: usize,
+    /// How many samples were recorded in total.  Events are stamped
+    /// with this.
+    samples_total: u64,
+    events: VecDeque<(u64, String)>,
+    /// When the modes were switched to.  The one the audio starts in
+    /// is kept too.
+    modes: VecDeque<(u64, String)>,
+}
+
+/// Keeps the last minutes of audio input, and the events that were
+/// detected in it, in memory.  On `dump` they are written to a WAV
+/// file and a JSON file next to it, which can be fed to `nf_analyze`
+/// to reproduce what happened.
+///
+/// The Recorder can be cloned, all clones share the same buffer.
+#[derive(Clone)]
+pub struct Recorder {
+    sample_rate: f32,
+    dir: PathBuf,
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl Recorder {
+    pub fn new(sample_rate: f32, duration: Duration, dir: &Path) -> Recorder {
+        let capacity = (duration.as_secs_f32() * sample_rate) as usize;
+        info!(
+            "Recording the last {:?} of audio, dumps go to {}.",
+            duration,
+            dir.display()
+        );
+        Recorder {
+            sample_rate,
+            dir: dir.to_path_buf(),
+            state: Arc::new(Mutex::new(RecorderState {
+                buffer: VecDeque::with_capacity(capacity),
+                capacity,
+                samples_total: 0,
+                events: VecDeque::new(),
+                modes: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub fn get_sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Returns a handler that records every frame, before it passes
+    /// it on to the given handler.
+    pub fn wrap(&self, handler: Box<dyn ValsHandler>) -> Box<dyn ValsHandler> {
+        Box::new(RecordingHandler {
+            recorder: self.clone(),
+            handler,
+        })
+    }
+
+    fn record(&self, frame: &[f32]) {
+        let mut state = self.state.lock().unwrap();
+        let len = state.buffer.len();
+        let overflow = (len + frame.len()).saturating_sub(state.capacity);
+        let skip = overflow.saturating_sub(len);
+        state.buffer.drain(..overflow.min(len));
+        state.buffer.extend(frame[skip.min(frame.len())..].iter());
+        state.samples_total += frame.len() as u64;
+        // forget events that are older than the audio
+        let t_start = state.samples_total - state.buffer.len() as u64;
+        while state
+            .events
+            .front()
+            .map(|(t, _)| *t < t_start)
+            .unwrap_or(false)
+        {
+            state.events.pop_front();
+        }
+        while state.modes.len() > 1 && state.modes[1].0 <= t_start {
+            state.modes.pop_front();
+        }
+    }
+
+    /// Remembers an event at the current position in the audio.
+    pub fn log_event(&self, event: &str) {
+        let mut state = self.state.lock().unwrap();
+        let t = state.samples_total;
+        state.events.push_back((t, event.to_string()));
+    }
+
+    /// Remembers that the lights switched to another mode, the events
+    /// after this come from it.
+    pub fn log_mode(&self, mode: &str) {
+        let mut state = self.state.lock().unwrap();
+        let t = state.samples_total;
+        state.modes.push_back((t, mode.to_string()));
+    }
+
+    /// Writes the recorded audio and events to
+    /// `<dir>/recording-<unix time in ms>.wav` and `.json`.  The files are
+    /// written in the background, so this can be called from input
+    /// handlers.
+    pub fn dump(&self) {
+        let (samples, events, modes) = {
+            let state = self.state.lock().unwrap();
+            let t_start = state.samples_total - state.buffer.len() as u64;
+            let stamp = |log: &VecDeque<(u64, String)>| -> Vec<(f64, String)> {
+                log.iter()
+                    .map(|(t, e)| {
+                        let t = t.saturating_sub(t_start);
+                        (t as f64 / self.sample_rate as f64, e.clone())
+                    })
+                    .collect()
+            };
+            (
+                state.buffer.iter().copied().collect::<Vec<f32>>(),
+                stamp(&state.events),
+                stamp(&state.modes),
+            )
+        };
+        let t_now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let wav_path = self.dir.join(format!("recording-{}.wav", t_now));
+        let sample_rate = self.sample_rate;
+        thread::spawn(move || {
+            match write_recording(&wav_path, sample_rate, &samples, &events, &modes) {
+                Ok(()) => info!("Dumped recording to {}.", wav_path.display()),
+                Err(e) => warn!("Failed to dump recording: {}", e),
+            }
+        });
+    }
+}
+
+fn write_recording(
+    wav_path: &Path,
+    sample_rate: f32,
+    samples: &[f32],
+    events: &[(f64, String)],
+    modes: &[(f64, String)],
+) -> Result<(), String> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    };
+    let mut writer = WavWriter::create(wav_path, spec).map_err(|e| e.to_string())?;
+    for sample in samples {
+        writer.write_sample(*sample).map_err(|e| e.to_string())?;
+    }
+    writer.finalize().map_err(|e| e.to_string())?;
+    let sidecar = json!({
+        "sample_rate": sample_rate,
+        "duration": samples.len() as f64 / sample_rate as f64,
+        "events": events
+            .iter()
+            .map(|(t, e)| json!({ "time": t, "event": e }))
+            .collect::<Vec<_>>(),
+        "modes": modes
+            .iter()
+            .map(|(t, m)| json!({ "time": t, "mode": m }))
+            .collect::<Vec<_>>(),
+    });
+    let file = File::create(wav_path.with_extension("json")).map_err(|e| e.to_string())?;
+    serde_json::to_writer_pretty(file, &sidecar).map_err(|e| e.to_string())
+}
+
+struct RecordingHandler {
+    recorder: Recorder,
+    handler: Box<dyn ValsHandler>,
+}
+
+impl ValsHandler for RecordingHandler {
+    fn take_frame(&mut self, frame: &[f32]) {
+        self.recorder.record(frame);
+        self.handler.take_frame(frame);
+    }
+
+    fn status_changed(&mut self, status: &SourceStatus) {
+        self.handler.status_changed(status);
+    }
+}
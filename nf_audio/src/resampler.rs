@@ -0,0 +1,114 @@
+use crate::{SourceStatus, ValsHandler};
+
+/// A streaming resampler using linear interpolation.  It is cheap and
+/// good enough for analysis, where only the band intensities matter.
+pub struct Resampler {
+    /// How far to advance in the input for every output sample.
+    step: f64,
+    /// Position of the next output sample.  0 is the last sample of
+    /// the previous frame, 1 the first sample of the current frame.
+    pos: f64,
+    last: f32,
+}
+
+impl Resampler {
+    pub fn new(from_rate: f32, to_rate: f32) -> Resampler {
+        Resampler {
+            step: from_rate as f64 / to_rate as f64,
+            pos: 1.,
+            last: 0.,
+        }
+    }
+
+    pub fn process(&mut self, frame: &[f32]) -> Vec<f32> {
+        let n = frame.len();
+        let mut res = Vec::with_capacity((n as f64 / self.step) as usize + 1);
+        while self.pos < n as f64 {
+            let i = self.pos.floor() as usize;
+            let frac = (self.pos - i as f64) as f32;
+            let a = if i == 0 { self.last } else { frame[i - 1] };
+            let b = frame[i];
+            res.push(a + (b - a) * frac);
+            self.pos += self.step;
+        }
+        if n > 0 {
+            self.pos -= n as f64;
+            self.last = frame[n - 1];
+        }
+        res
+    }
+}
+
+/// Resamples all frames before passing them on to the wrapped
+/// handler.
+pub struct ResamplingHandler {
+    resampler: Resampler,
+    handler: Box<dyn ValsHandler>,
+}
+
+impl ResamplingHandler {
+    pub fn new(from_rate: f32, to_rate: f32, handler: Box<dyn ValsHandler>) -> ResamplingHandler {
+        ResamplingHandler {
+            resampler: Resampler::new(from_rate, to_rate),
+            handler,
+        }
+    }
+}
+
+impl ValsHandler for ResamplingHandler {
+    fn take_frame(&mut self, frame: &[f32]) {
+        let frame = self.resampler.process(frame);
+        if !frame.is_empty() {
+            self.handler.take_frame(&frame);
+        }
+    }
+
+    fn status_changed(&mut self, status: &SourceStatus) {
+        self.handler.status_changed(status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Resamples a ramp, whose samples are their own index, in frames
+    /// of 100 samples.
+    fn resample_ramp(from_rate: f32, to_rate: f32, n: usize) -> Vec<f32> {
+        let ramp: Vec<f32> = (0..n).map(|i| i as f32).collect();
+        let mut resampler = Resampler::new(from_rate, to_rate);
+        ramp.chunks(100)
+            .flat_map(|frame| resampler.process(frame))
+            .collect()
+    }
+
+    #[test]
+    fn output_length_follows_the_rate() {
+        for (from_rate, to_rate) in &[(48000., 44100.), (44100., 48000.), (48000., 16000.)] {
+            let out = resample_ramp(*from_rate, *to_rate, 48000);
+            let expected = 48000. * to_rate / from_rate;
+            assert!(
+                (out.len() as f32 - expected).abs() <= 1.,
+                "{} -> {}",
+                from_rate,
+                to_rate
+            );
+        }
+    }
+
+    #[test]
+    fn samples_are_at_the_new_rate() {
+        // across the frame boundaries too
+        let step = 48000. / 44100.;
+        for (i, v) in resample_ramp(48000., 44100., 1000).iter().enumerate() {
+            assert!((v - i as f32 * step).abs() < 1e-3, "sample {}: {}", i, v);
+        }
+    }
+
+    #[test]
+    fn same_rate_passes_through() {
+        // the last sample is held back until the next frame
+        let ramp: Vec<f32> = (0..299).map(|i| i as f32).collect();
+        assert_eq!(resample_ramp(44100., 44100., 300), ramp);
+    }
+}
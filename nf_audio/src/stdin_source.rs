@@ -0,0 +1,200 @@
+use crate::{resample_if_needed, AudioSource, ChannelSelection, ValsHandler};
+use cpal::Sample;
+use log::{info, warn};
+use std::io::{self, Read};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use stoppable_thread::{spawn, StoppableHandle};
+
+/// How many samples per channel are read and delivered at once.
+const FRAME_SIZE: usize = 512;
+
+/// Sample formats of raw PCM, named like in ffmpeg and arecord.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PcmFormat {
+    S16LE,
+    U16LE,
+    S32LE,
+    F32LE,
+}
+
+impl FromStr for PcmFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "s16le" | "s16_le" => Ok(PcmFormat::S16LE),
+            "u16le" | "u16_le" => Ok(PcmFormat::U16LE),
+            "s32le" | "s32_le" => Ok(PcmFormat::S32LE),
+            "f32le" | "float_le" => Ok(PcmFormat::F32LE),
+            other => Err(format!("Unknown PCM format: {}", other)),
+        }
+    }
+}
+
+impl PcmFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PcmFormat::S16LE | PcmFormat::U16LE => 2,
+            PcmFormat::S32LE | PcmFormat::F32LE => 4,
+        }
+    }
+
+    /// Decodes a single sample and normalizes it to [-1, 1].
+    pub fn decode(&self, bytes: &[u8]) -> f32 {
+        match self {
+            PcmFormat::S16LE => i16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+            PcmFormat::U16LE => u16::from_le_bytes([bytes[0], bytes[1]]).to_f32(),
+            PcmFormat::S32LE => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+                    / -(i32::MIN as f32)
+            }
+            PcmFormat::F32LE => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Reads raw interleaved PCM from stdin, for example piped from
+/// `arecord -f cd` or `ffmpeg -f s16le -`.  The audio arrives as fast
+/// as it is written to the pipe.
+pub struct StdinAudioSource {
+    sample_rate: f32,
+    channels: usize,
+    format: PcmFormat,
+    channel_selection: ChannelSelection,
+    target_sample_rate: Option<f32>,
+    finished: Arc<AtomicBool>,
+    thread: Option<StoppableHandle<()>>,
+}
+
+impl StdinAudioSource {
+    pub fn new(sample_rate: f32, channels: usize, format: PcmFormat) -> StdinAudioSource {
+        StdinAudioSource {
+            sample_rate,
+            channels,
+            format,
+            channel_selection: ChannelSelection::default(),
+            target_sample_rate: None,
+            finished: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        }
+    }
+}
+
+impl AudioSource for StdinAudioSource {
+    fn set_channel_selection(&mut self, channel_selection: ChannelSelection) {
+        self.channel_selection = channel_selection;
+    }
+
+    fn set_target_sample_rate(&mut self, sample_rate: Option<f32>) {
+        self.target_sample_rate = sample_rate;
+    }
+
+    fn get_sample_rate(&self) -> f32 {
+        self.target_sample_rate.unwrap_or(self.sample_rate)
+    }
+
+    fn start_processing(&mut self, vals_handler: Box<dyn ValsHandler>) {
+        let mut vals_handler =
+            resample_if_needed(self.sample_rate, self.get_sample_rate(), vals_handler);
+        let channels = self.channels;
+        let format = self.format;
+        let channel_selection = self.channel_selection;
+        let finished = Arc::clone(&self.finished);
+        info!(
+            "Reading {:?} PCM from stdin ({} Hz, {} channels).",
+            format, self.sample_rate, channels
+        );
+        self.thread = Some(spawn(move |stopped| {
+            let bytes_per_frame = format.bytes_per_sample() * channels;
+            let mut buf = vec![0u8; FRAME_SIZE * bytes_per_frame];
+            let stdin = io::stdin();
+            let mut stdin = stdin.lock();
+            while !stopped.get() {
+                // read a full buffer, unless the input ends early
+                let mut n = 0;
+                while n < buf.len() {
+                    match stdin.read(&mut buf[n..]) {
+                        Ok(0) => break,
+                        Ok(k) => n += k,
+                        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                        Err(e) => {
+                            warn!("Error reading from stdin: {}", e);
+                            break;
+                        }
+                    }
+                }
+                let n = n - n % bytes_per_frame;
+                if n > 0 {
+                    let samples: Vec<f32> = buf[..n]
+                        .chunks(format.bytes_per_sample())
+                        .map(|bytes| format.decode(bytes))
+                        .collect();
+                    vals_handler.take_frame(&channel_selection.to_mono(&samples, channels));
+                }
+                if n < buf.len() {
+                    break;
+                }
+            }
+            info!("Input on stdin ended.");
+            finished.store(true, Ordering::SeqCst);
+        }));
+    }
+
+    fn stop_processing(&mut self) {
+        info!("Stopping processing.");
+        // the thread might be blocked reading stdin, so it is not joined
+        if let Some(thread) = self.thread.take() {
+            thread.stop();
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("S16_LE".parse(), Ok(PcmFormat::S16LE));
+        assert_eq!("float_le".parse(), Ok(PcmFormat::F32LE));
+        assert!("s24le".parse::<PcmFormat>().is_err());
+    }
+
+    #[test]
+    fn s16_is_normalized() {
+        let f = PcmFormat::S16LE;
+        assert_eq!(f.decode(&i16::MIN.to_le_bytes()), -1.);
+        assert_eq!(f.decode(&0i16.to_le_bytes()), 0.);
+        assert_eq!(f.decode(&i16::MAX.to_le_bytes()), 1.);
+    }
+
+    #[test]
+    fn u16_is_normalized() {
+        let f = PcmFormat::U16LE;
+        assert_eq!(f.decode(&0u16.to_le_bytes()), -1.);
+        assert_eq!(f.decode(&32768u16.to_le_bytes()), 0.);
+        assert_eq!(f.decode(&u16::MAX.to_le_bytes()), 1.);
+    }
+
+    #[test]
+    fn s32_is_normalized() {
+        let f = PcmFormat::S32LE;
+        assert_eq!(f.decode(&i32::MIN.to_le_bytes()), -1.);
+        assert_eq!(f.decode(&0i32.to_le_bytes()), 0.);
+        assert_eq!(f.decode(&i32::MAX.to_le_bytes()), 1.);
+    }
+
+    #[test]
+    fn f32_is_kept() {
+        let f = PcmFormat::F32LE;
+        for v in &[-1., -0.25, 0., 1.] {
+            assert_eq!(f.decode(&f32::to_le_bytes(*v)), *v);
+        }
+    }
+}
@@ -1,6 +1,6 @@
 mod ui;
 use clap::{App, Arg};
-use nf_audio::CpalAudioGetter;
+use nf_audio::{list_devices, open_source};
 use nightfire::audio;
 
 fn main() {
@@ -9,7 +9,18 @@ fn main() {
         .arg(Arg::with_name("q").short("q").takes_value(true))
         .arg(Arg::with_name("n").short("n").takes_value(true))
         .arg(Arg::with_name("device").short("d").takes_value(true))
+        .arg(Arg::with_name("input").long("input").takes_value(true))
+        .arg(Arg::with_name("dc-block").long("dc-block"))
+        .arg(Arg::with_name("notch").long("notch").takes_value(true))
+        .arg(Arg::with_name("harmonics").long("harmonics").takes_value(true))
+        .arg(Arg::with_name("gate").long("gate").takes_value(true))
+        .arg(Arg::with_name("list-devices").long("list-devices"))
+        .arg(Arg::with_name("json").long("json").requires("list-devices"))
         .get_matches();
+    if matches.is_present("list-devices") {
+        list_devices(matches.is_present("json"));
+        return;
+    }
     let q = matches
         .value_of("q")
         .map(|v| v.parse().unwrap())
@@ -18,8 +29,30 @@ fn main() {
         .value_of("n")
         .map(|v| v.parse().unwrap())
         .unwrap_or(30);
-    let device_name = matches.value_of("device").unwrap_or("default").to_string();
-    let mut audio_getter = CpalAudioGetter::new(device_name);
+    // --input takes any source, e.g. file:track.wav, -d only device names
+    let input = match matches.value_of("input") {
+        Some(spec) => spec.to_string(),
+        None => format!("cpal:{}", matches.value_of("device").unwrap_or("default")),
+    };
+    // input conditioning, can be bypassed in the window with [B]
+    let preprocessing = audio::PreprocessorParams {
+        bypass: false,
+        dc_block: matches.is_present("dc-block"),
+        hum_notch: matches.value_of("notch").map(|v| audio::HumNotchParams {
+            f_mains: v.parse().unwrap(),
+            harmonics: matches
+                .value_of("harmonics")
+                .map(|v| v.parse().unwrap())
+                .unwrap_or(4),
+            q: 30.,
+        }),
+        noise_gate: matches.value_of("gate").map(|v| audio::NoiseGateParams {
+            threshold: v.parse().unwrap(),
+            attack: 0.005,
+            release: 0.2,
+        }),
+    };
+    let mut audio_getter = open_source(&input);
     let sample_rate = audio_getter.get_sample_rate();
     // prepare processor
     let filter = audio::SignalFilter::new(20., 20_000., sample_rate, q, n_filters);
@@ -31,7 +64,8 @@ fn main() {
         sample_freq,
         handler,
     );
-    let mut proc = ui::EqViz::new(sig_proc);
+    let preprocessor = audio::Preprocessor::new(sample_rate, &preprocessing);
+    let mut proc = ui::EqViz::new(sig_proc, preprocessor);
     let state = proc.get_shared_vals();
 
     audio_getter.start_processing(Box::new(proc));
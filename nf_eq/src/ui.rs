@@ -8,6 +8,7 @@ pub struct DisplayData {
     pub frequency_bins: Vec<f32>,
     pub audio_features: audio::AudioFeatures,
     pub normalize: bool,
+    pub bypass_preprocessing: bool,
 }
 
 impl DisplayData {
@@ -15,22 +16,30 @@ impl DisplayData {
         return DisplayData {
             frequency_bins: vec![0.; frequency_bin_count],
             audio_features: audio::AudioFeatures::new(),
-            normalize: normalize
+            normalize: normalize,
+            bypass_preprocessing: false,
         };
     }
 }
 
 pub struct EqViz {
     sig_proc: audio::SigProc<audio::DefaultSampleHandler>,
+    preprocessor: audio::Preprocessor,
     display_data: Arc<Mutex<DisplayData>>,
 }
 
 impl EqViz {
-    pub fn new(signal_processor: audio::SigProc<audio::DefaultSampleHandler>) -> EqViz {
+    pub fn new(
+        signal_processor: audio::SigProc<audio::DefaultSampleHandler>,
+        preprocessor: audio::Preprocessor,
+    ) -> EqViz {
         let n = signal_processor.filter.num_filters();
+        let mut display_data = DisplayData::new(n, false);
+        display_data.bypass_preprocessing = preprocessor.is_bypassed();
         EqViz {
             sig_proc: signal_processor,
-            display_data: Arc::new(Mutex::new(DisplayData::new(n, false))),
+            preprocessor,
+            display_data: Arc::new(Mutex::new(display_data)),
         }
     }
 
@@ -46,7 +55,10 @@ impl nf_audio::ValsHandler for EqViz {
         // internal state.
 
         // println!("{}", frame.len());
-        self.sig_proc.add_audio_frame(frame);
+        let bypass = self.display_data.lock().unwrap().bypass_preprocessing;
+        self.preprocessor.set_bypass(bypass);
+        let frame = self.preprocessor.process_frame(frame);
+        self.sig_proc.add_audio_frame(&frame);
         let n = self.sig_proc.filter.num_filters();
         let mut vals = Vec::with_capacity(n);
         for i in 0..n {
@@ -73,6 +85,10 @@ pub fn create_window(display_data: Arc<Mutex<DisplayData>>) {
         .unwrap();
     window.set_max_fps(50);
     while let Some(event) = window.next() {
+        if let Some(Button::Keyboard(Key::B)) = event.press_args() {
+            let mut dd = display_data.lock().unwrap();
+            dd.bypass_preprocessing = !dd.bypass_preprocessing;
+        }
         let w = window.size().width;
         let h = window.size().height;
         let dd = display_data.lock().unwrap();
@@ -94,6 +110,10 @@ pub fn create_window(display_data: Arc<Mutex<DisplayData>>) {
                 context.transform,
                 graphics,
             );
+            // marker in the corner while the preprocessing is bypassed
+            if dd.bypass_preprocessing {
+                rectangle(alt_fg_color, [0., 0., 10., 10.], context.transform, graphics);
+            }
             for i in 0..n {
                 let v = dd.frequency_bins[i] as f64;
                 let i = i as f64;
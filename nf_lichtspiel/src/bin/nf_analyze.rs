@@ -0,0 +1,124 @@
+//! Runs the audio analysis on a recording and prints the detected
+//! events as JSON, in the same format as the sidecar files that the
+//! recorder writes.  If such a sidecar file is next to the recording,
+//! the events are compared to the ones that were detected live.  Live,
+//! only the modes that react to the audio log events, the modes the
+//! sidecar lists are printed with the comparison.
+use clap::{App, Arg};
+use nf_audio::{open_source, ValsHandler};
+use nf_lichtspiel::conf::load_preprocessing;
+use nightfire::audio::{Preprocessor, SignalProcessor};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+
+struct EventCollector {
+    preprocessor: Preprocessor,
+    signal_processor: SignalProcessor,
+    sample_rate: f32,
+    samples_total: u64,
+    events: Arc<Mutex<Vec<(f64, String)>>>,
+}
+
+impl ValsHandler for EventCollector {
+    fn take_frame(&mut self, frame: &[f32]) {
+        // stamped like in the recorder, at the end of the frame
+        self.samples_total += frame.len() as u64;
+        let t = self.samples_total as f64 / self.sample_rate as f64;
+        let frame = self.preprocessor.process_frame(frame);
+        let mut events = self.events.lock().unwrap();
+        for event in self.signal_processor.add_audio_frame(&frame) {
+            if let Some(label) = event.label() {
+                events.push((t, label));
+            }
+        }
+    }
+}
+
+fn count_labels<'a>(labels: impl Iterator<Item = &'a str>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for label in labels {
+        *counts.entry(label.to_string()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    env_logger::init();
+    let matches = App::new("nf_analyze")
+        .arg(Arg::with_name("recording").required(true))
+        .arg(
+            Arg::with_name("conf")
+                .long("conf")
+                .takes_value(true)
+                .help("Config file to take the preprocessing settings from"),
+        )
+        .get_matches();
+    let path = matches.value_of("recording").unwrap();
+    let preprocessing = matches
+        .value_of("conf")
+        .map(|conf| load_preprocessing(Path::new(conf)))
+        .unwrap_or_default();
+    let mut audio_getter = open_source(&format!("file-fast:{}", path));
+    let sample_rate = audio_getter.get_sample_rate();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    audio_getter.start_processing(Box::new(EventCollector {
+        preprocessor: Preprocessor::new(sample_rate, &preprocessing),
+        signal_processor: SignalProcessor::new(sample_rate, 50.),
+        sample_rate,
+        samples_total: 0,
+        events: Arc::clone(&events),
+    }));
+    while !audio_getter.is_finished() {
+        thread::sleep(time::Duration::from_millis(50));
+    }
+    audio_getter.stop_processing();
+    let events = events.lock().unwrap();
+    let output = json!({
+        "sample_rate": sample_rate,
+        "events": events
+            .iter()
+            .map(|(t, e)| json!({ "time": t, "event": e }))
+            .collect::<Vec<_>>(),
+    });
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    // compare to what was detected live
+    let sidecar_path = Path::new(path).with_extension("json");
+    if let Ok(sidecar) = std::fs::read_to_string(&sidecar_path) {
+        let sidecar: Value = serde_json::from_str(&sidecar).expect("Invalid sidecar file");
+        let live = count_labels(
+            sidecar["events"]
+                .as_array()
+                .expect("Sidecar file has no events")
+                .iter()
+                .filter_map(|e| e["event"].as_str()),
+        );
+        let offline = count_labels(events.iter().map(|(_, e)| e.as_str()));
+        if let Some(modes) = sidecar["modes"].as_array() {
+            let modes: Vec<String> = modes
+                .iter()
+                .map(|m| {
+                    format!(
+                        "{} from {:.1} s",
+                        m["mode"].as_str().unwrap_or("?"),
+                        m["time"].as_f64().unwrap_or(0.)
+                    )
+                })
+                .collect();
+            eprintln!("live modes: {}", modes.join(", "));
+        }
+        eprintln!("{:<20} {:>8} {:>8}", "event", "live", "offline");
+        let labels: std::collections::BTreeSet<&String> =
+            live.keys().chain(offline.keys()).collect();
+        for label in labels {
+            eprintln!(
+                "{:<20} {:>8} {:>8}",
+                label,
+                live.get(label).unwrap_or(&0),
+                offline.get(label).unwrap_or(&0)
+            );
+        }
+    }
+}
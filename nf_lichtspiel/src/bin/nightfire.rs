@@ -1,18 +1,39 @@
 use clap::{App, Arg, ArgMatches};
 use dualshock3::read_controller;
-use nf_audio::CpalAudioGetter;
+use nf_audio::{list_devices, open_source, AudioSource, FallbackSource, Recorder};
 use nf_lichtspiel::conf::Conf;
 use nf_lichtspiel::mode::Main;
+use nf_lichtspiel::osc::start_osc_thread;
+use nf_lichtspiel::output::start_output_thread;
 use nf_lichtspiel::periodic_updater::start_periodic_update_thread;
-use nf_lichtspiel::piblaster::start_piblaster_thread;
+use nightfire::audio::Preprocessor;
 #[cfg(feature = "piston-ui")]
 use nf_lichtspiel::ui::piston::run_piston_thread;
 use pi_ir_remote::read_ir_remote;
-use std::{error, thread, time};
+use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::iterator::Signals;
+use std::{error, thread};
 
 fn get_args() -> ArgMatches<'static> {
     App::new("lumi")
         .arg(Arg::with_name("debug").long("debug"))
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .help("Audio input, overrides audio-in, e.g. file:track.wav"),
+        )
+        .arg(
+            Arg::with_name("list-devices")
+                .long("list-devices")
+                .help("Lists the audio inputs and exits"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .requires("list-devices")
+                .help("Lists the audio inputs as JSON"),
+        )
         .get_matches()
 }
 
@@ -22,37 +43,83 @@ fn main() -> Result<(), Box<dyn error::Error>> {
     env_logger::init();
     // read commandline arguments
     let matches = get_args();
+    if matches.is_present("list-devices") {
+        list_devices(matches.is_present("json"));
+        return Ok(());
+    }
     // read config
     let conf = Conf::new();
     // open audio client
-    let mut audio_getter = match conf.audio_in {
-        Some(dev_name) => CpalAudioGetter::new(dev_name),
-        None => panic!("No audio-in option given!"),
+    let mut audio_getter: Box<dyn AudioSource> = match matches.value_of("input") {
+        Some(spec) => open_source(spec),
+        None if conf.audio_in.is_empty() => panic!("No audio-in option given!"),
+        // falls back to the next device and reconnects if one fails
+        None => {
+            let mut source = FallbackSource::new(conf.audio_in);
+            source.set_frame_timeout(conf.audio_timeout);
+            Box::new(source)
+        }
     };
+    audio_getter.set_channel_selection(conf.audio_channel);
+    audio_getter.set_target_sample_rate(conf.audio_sample_rate);
     let sample_rate = audio_getter.get_sample_rate();
     // setup state
     let mut main = Main::new(sample_rate);
+    if let Some(text) = &conf.text {
+        main.set_text(text);
+    }
+    let luts = conf.luts;
+    if let Some(luts) = &luts {
+        main.set_luts(luts.clone());
+    }
+    // the LUT grades everything, the simulator shows it too
+    let grade = |color_map| match &luts {
+        Some(luts) => luts.wrap(color_map),
+        None => color_map,
+    };
+    let recorder = conf
+        .recorder
+        .map(|rec_conf| Recorder::new(sample_rate, rec_conf.duration, &rec_conf.dir));
+    if let Some(recorder) = &recorder {
+        main.set_recorder(recorder.clone());
+        // `kill -USR1` dumps the recording too
+        let dump = main.new_recording_dumper();
+        let mut signals = Signals::new([SIGUSR1])?;
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                dump();
+            }
+        });
+    }
     let controller = read_controller(main.new_controller_handler());
-    audio_getter.start_processing(main.new_audio_handler());
-    if cfg!(feature = "pi-blaster") {
-        let piblaster = start_piblaster_thread(conf.lights, main.new_color_map(), 50);
+    let preprocessor = Preprocessor::new(sample_rate, &conf.preprocessing);
+    let audio_handler = main.new_audio_handler(preprocessor);
+    audio_getter.start_processing(match &recorder {
+        Some(recorder) => recorder.wrap(audio_handler),
+        None => audio_handler,
+    });
+    if let Some(port) = conf.osc_port {
+        let osc = start_osc_thread(main.new_osc_handler(), port);
     }
+    let output = start_output_thread(
+        conf.outputs,
+        conf.layout.clone(),
+        grade(main.new_color_map()),
+        conf.timing,
+    );
     start_periodic_update_thread(main.new_periodic_update_handler(), 50);
     if cfg!(feature = "ir-remote") {
         read_ir_remote(4, main.new_ir_remote_handler());
     }
     #[cfg(feature = "piston-ui")]
-    {
-        run_piston_thread(main.new_color_map());
-        Ok(())
-    }
+    run_piston_thread(grade(main.new_color_map()), conf.layout, conf.curve);
     #[cfg(not(feature = "piston-ui"))]
     {
-        loop {
-            let dur = time::Duration::from_millis(10000);
-            thread::sleep(dur);
-        }
+        // run until stopped, e.g. by systemd
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        signals.forever().next();
     }
-    // piblaster.stop();
-    // Ok(())
+    // switch off the lights and end network streams cleanly
+    output.stop().join().unwrap();
+    Ok(())
 }
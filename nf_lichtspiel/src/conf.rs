@@ -1,23 +1,863 @@
-use crate::lightid::LightId;
-use crate::piblaster as pb;
+use crate::layout::{Layout, Light, LightId, Matrix, Shape};
+use crate::light::{Coordinate, PinValue};
+use crate::output::artnet::ArtNet;
+use crate::output::calibration::Calibration;
+use crate::output::dmx::{DmxLight, DmxMapping};
+use crate::output::fixture::{Aim, Channel, Profile};
+use crate::output::ddp::Ddp;
+use crate::output::gpio::GpioPwm;
+use crate::output::lut::{Lut3d, LutSelection};
+use crate::output::opc::{Opc, OPC_PORT};
+use crate::output::piblaster as pb;
+use crate::output::pixels::{PixelLight, PixelMapping};
+use crate::output::power::{Current, PowerLimiter};
+use crate::output::pwm::{SysfsPwm, SYSFS_PWM};
+use crate::output::sacn::{parse_cid, Sacn, SacnSource};
+use crate::output::scheduler::{FrameTiming, Paced};
+use crate::output::serial::Serial;
+use crate::output::transfer::{Curve, Transfer};
+use crate::output::white::WhiteLeds;
+use crate::output::{OutputBackend, PinLight};
 use log;
-use std::path::Path;
+use nf_audio::{ChannelSelection, FRAME_TIMEOUT};
+use nightfire::audio::{HumNotchParams, NoiseGateParams, PreprocessorParams};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use yaml_rust::Yaml;
 
 pub struct Conf {
-    pub lights: pb::Lights,
-    pub audio_in: Option<String>,
+    pub layout: Layout,
+    pub outputs: Vec<Box<dyn OutputBackend>>,
+    /// The default brightness curve, also used by the simulator.
+    pub curve: Curve,
+    /// Audio sources in order of preference, empty if audio is off.
+    pub audio_in: Vec<String>,
+    pub audio_channel: ChannelSelection,
+    pub audio_sample_rate: Option<f32>,
+    /// How long a source may be silent before the next one is tried,
+    /// None to wait for the source to report a failure.
+    pub audio_timeout: Option<Duration>,
+    pub preprocessing: PreprocessorParams,
+    pub recorder: Option<RecorderConf>,
+    /// UDP port to listen for OSC control messages on.
+    pub osc_port: Option<u16>,
+    /// The text of the text mode.
+    pub text: Option<String>,
+    pub luts: Option<LutSelection>,
+    pub timing: FrameTiming,
 }
 
-fn str_to_light_id(str: &str) -> LightId {
-    match str.as_ref() {
-        "Top" => LightId::Top,
-        "Bottom" => LightId::Bottom,
-        "Left" => LightId::Left,
-        "Right" => LightId::Right,
-        other => panic!("Unknown light ID: {}", other),
+/// Settings for the rolling audio recorder.
+pub struct RecorderConf {
+    pub duration: Duration,
+    pub dir: PathBuf,
+}
+
+fn yaml_to_f32(yaml: &Yaml, default: f32) -> f32 {
+    match yaml {
+        Yaml::Real(_) => yaml.as_f64().unwrap() as f32,
+        Yaml::Integer(i) => *i as f32,
+        Yaml::BadValue => default,
+        other => panic!("Expected a number, got: {:?}", other),
+    }
+}
+
+/// Reads the optional input conditioning.  Example:
+///
+/// ```yaml
+/// preprocessing:
+///   bypass: false
+///   dc-block: true
+///   hum-notch:
+///     freq: 50
+///     harmonics: 4
+///     q: 30
+///   noise-gate:
+///     threshold: -60
+///     attack: 0.005
+///     release: 0.2
+/// ```
+fn parse_preprocessing(yaml: &Yaml) -> PreprocessorParams {
+    if yaml.is_badvalue() {
+        return PreprocessorParams::default();
+    }
+    let hum = &yaml["hum-notch"];
+    let gate = &yaml["noise-gate"];
+    PreprocessorParams {
+        bypass: yaml["bypass"].as_bool().unwrap_or(false),
+        dc_block: yaml["dc-block"].as_bool().unwrap_or(false),
+        hum_notch: if hum.is_badvalue() {
+            None
+        } else {
+            Some(HumNotchParams {
+                f_mains: yaml_to_f32(&hum["freq"], 50.),
+                harmonics: hum["harmonics"].as_i64().unwrap_or(4) as usize,
+                q: yaml_to_f32(&hum["q"], 30.),
+            })
+        },
+        noise_gate: if gate.is_badvalue() {
+            None
+        } else {
+            Some(NoiseGateParams {
+                threshold: yaml_to_f32(&gate["threshold"], -60.),
+                attack: yaml_to_f32(&gate["attack"], 0.005),
+                release: yaml_to_f32(&gate["release"], 0.2),
+            })
+        },
+    }
+}
+
+/// Reads the optional recorder section.  Example:
+///
+/// ```yaml
+/// recorder:
+///   minutes: 5
+///   dir: /opt/nightfire/recordings
+/// ```
+fn parse_recorder(yaml: &Yaml) -> Option<RecorderConf> {
+    if yaml.is_badvalue() {
+        return None;
+    }
+    let minutes = yaml_to_f32(&yaml["minutes"], 5.);
+    if minutes <= 0. {
+        panic!("The recorder needs more than 0 minutes");
+    }
+    Some(RecorderConf {
+        duration: Duration::from_secs_f32(minutes * 60.),
+        dir: PathBuf::from(yaml["dir"].as_str().unwrap_or(".")),
+    })
+}
+
+/// Reads the 3D LUTs, by name in the order they are switched through.
+/// Relative paths are from the directory of the config.  Example:
+///
+/// ```yaml
+/// luts:
+///   warm: luts/warm.cube
+///   night: luts/night.cube
+/// lut: warm
+/// ```
+fn parse_luts(conf: &Yaml, dir: &Path) -> Option<LutSelection> {
+    let luts = conf["luts"].as_hash()?;
+    let selection = LutSelection::new(
+        luts.iter()
+            .map(|(name, path)| {
+                let name = name.as_str().expect("LUT names must be strings");
+                let path = path.as_str().expect("Expected the path of a .cube file");
+                let lut = Lut3d::load(&dir.join(path)).unwrap();
+                (name.to_string(), lut)
+            })
+            .collect(),
+    );
+    if let Some(name) = conf["lut"].as_str() {
+        selection.select(name).unwrap();
+    }
+    Some(selection)
+}
+
+/// Reads only the preprocessing section of a config file, for tools
+/// that replay audio the same way `nightfire` processed it.
+pub fn load_preprocessing(path: &Path) -> PreprocessorParams {
+    let yaml_str = std::fs::read_to_string(path).expect("Error reading config file.");
+    let docs = yaml_rust::YamlLoader::load_from_str(&yaml_str).expect("Error parsing config file.");
+    parse_preprocessing(&docs[0]["preprocessing"])
+}
+
+/// Reads a single source spec or a list of them.
+fn yaml_to_specs(yaml: &Yaml) -> Vec<String> {
+    match yaml {
+        Yaml::String(s) => vec![s.to_string()],
+        Yaml::Array(list) => list
+            .iter()
+            .map(|spec| spec.as_str().expect("Expected an audio source name").to_string())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn find_light<'a>(layout: &'a Layout, name: &str) -> &'a Light {
+    match layout.get(&LightId::get(name)) {
+        Some(light) => light,
+        None => panic!("Unknown light: {}, lights are defined in the layout", name),
+    }
+}
+
+fn yaml_to_coordinate(yaml: &Yaml) -> Coordinate {
+    match yaml.as_vec() {
+        Some(xy) if xy.len() == 2 => Coordinate(
+            yaml_to_f32(&xy[0], 0.) as f64,
+            yaml_to_f32(&xy[1], 0.) as f64,
+        ),
+        _ => Coordinate(
+            yaml_to_f32(&yaml["x"], 0.) as f64,
+            yaml_to_f32(&yaml["y"], 0.) as f64,
+        ),
+    }
+}
+
+/// Reads the shape of a light, a point if no `path` or `arc` is given.
+fn parse_shape(name: &str, yaml: &Yaml) -> Shape {
+    if let Some(points) = yaml["path"].as_vec() {
+        if points.is_empty() {
+            panic!("The path of {} needs at least one point", name);
+        }
+        Shape::Path(points.iter().map(yaml_to_coordinate).collect())
+    } else if !yaml["arc"].is_badvalue() {
+        let arc = &yaml["arc"];
+        Shape::Arc {
+            center: yaml_to_coordinate(arc),
+            radius: yaml_to_f32(&arc["radius"], 1.) as f64,
+            from: yaml_to_f32(&arc["from"], 0.) as f64,
+            to: yaml_to_f32(&arc["to"], 360.) as f64,
+        }
+    } else {
+        Shape::Point(yaml_to_coordinate(yaml))
+    }
+}
+
+/// Reads a matrix, by default it covers the whole coordinate space.
+fn parse_matrix(yaml: &Yaml) -> Matrix {
+    let size = match yaml["size"].as_vec() {
+        Some(wh) if wh.len() == 2 => (
+            yaml_to_f32(&wh[0], 2.) as f64,
+            yaml_to_f32(&wh[1], 2.) as f64,
+        ),
+        _ => (2., 2.),
+    };
+    Matrix {
+        width: yaml["width"].as_i64().expect("Matrix without width") as usize,
+        height: yaml["height"].as_i64().expect("Matrix without height") as usize,
+        wiring: yaml["wiring"]
+            .as_str()
+            .unwrap_or("serpentine")
+            .parse()
+            .unwrap(),
+        rotation: yaml["rotation"].as_i64().unwrap_or(0) as u16,
+        center: yaml_to_coordinate(yaml),
+        size,
+    }
+}
+
+fn yaml_to_rgb(yaml: &Yaml, default: PinValue) -> [PinValue; 3] {
+    match yaml.as_vec() {
+        Some(rgb) if rgb.len() == 3 => [
+            yaml_to_f32(&rgb[0], default as f32) as PinValue,
+            yaml_to_f32(&rgb[1], default as f32) as PinValue,
+            yaml_to_f32(&rgb[2], default as f32) as PinValue,
+        ],
+        Some(_) => panic!("Expected three values for r, g and b"),
+        None => [default; 3],
+    }
+}
+
+/// Reads the color correction of a light.  Example:
+///
+/// ```yaml
+/// calibration:
+///   matrix: [[1, 0, 0], [0, 0.95, 0.05], [0, 0, 1]]
+///   gains: [1, 0.85, 0.7]
+///   max: 0.8
+/// ```
+fn parse_calibration(yaml: &Yaml) -> Calibration {
+    let default = Calibration::default();
+    let matrix = match yaml["matrix"].as_vec() {
+        Some(rows) if rows.len() == 3 => [
+            yaml_to_rgb(&rows[0], 0.),
+            yaml_to_rgb(&rows[1], 0.),
+            yaml_to_rgb(&rows[2], 0.),
+        ],
+        Some(_) => panic!("A calibration matrix has three rows"),
+        None => default.matrix,
+    };
+    Calibration {
+        matrix,
+        gains: yaml_to_rgb(&yaml["gains"], 1.),
+        max: yaml_to_f32(&yaml["max"], 1.) as PinValue,
+    }
+}
+
+/// Reads the lights and their positions.  Example:
+///
+/// ```yaml
+/// layout:
+///   Top: {x: -1, y: 1, tags: [wall]}
+///   Bottom: {x: 1, y: -1}
+///   Strip:
+///     path: [[-1, 1], [1, 1], [1, -1]]
+///     pixels: 60
+///   Ring:
+///     arc: {x: 0, y: 0, radius: 0.5, from: 0, to: 360}
+///     pixels: 24
+///   Panel:
+///     matrix: {width: 16, height: 16, wiring: serpentine, rotation: 90}
+///     calibration: {gains: [1, 0.9, 0.8]}
+/// ```
+///
+/// Without a layout, the four lights Top, Bottom, Left and Right of
+/// the original setup are used.
+fn parse_layout(yaml: &Yaml) -> Layout {
+    let lights = match yaml.as_hash() {
+        Some(lights) => lights,
+        None => return Layout::default(),
+    };
+    Layout::new(
+        lights
+            .iter()
+            .map(|(name, light)| {
+                let name = name.as_str().expect("Light names must be strings");
+                let tags = light["tags"]
+                    .as_vec()
+                    .map(|tags| {
+                        tags.iter()
+                            .map(|t| t.as_str().expect("Tags must be strings").to_string())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let calibration = parse_calibration(&light["calibration"]);
+                if light["matrix"].is_badvalue() {
+                    let n_pixels = light["pixels"].as_i64().unwrap_or(1) as usize;
+                    Light {
+                        tags,
+                        calibration,
+                        ..Light::new(name, parse_shape(name, light), n_pixels)
+                    }
+                } else {
+                    Light {
+                        tags,
+                        calibration,
+                        ..Light::matrix(name, parse_matrix(&light["matrix"]))
+                    }
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Reads the color temperatures of the white LEDs of a light, a
+/// number for a single white or `[warm, cold]` for a tunable one.
+fn parse_whites(yaml: &Yaml, default: WhiteLeds) -> WhiteLeds {
+    match yaml {
+        Yaml::BadValue => default,
+        Yaml::Array(list) if list.len() == 2 => WhiteLeds::tunable(
+            yaml_to_f32(&list[0], 2700.) as f64,
+            yaml_to_f32(&list[1], 6500.) as f64,
+        ),
+        Yaml::Array(_) => panic!("Expected the Kelvin of a warm and a cold white"),
+        kelvin => WhiteLeds::single(yaml_to_f32(kelvin, 6500.) as f64),
+    }
+}
+
+/// Reads the pin numbers of each light, for pi-blaster, PWM and GPIO.
+/// A white LED is on `w`, a warm and a cold white on `ww` and `cw`,
+/// with their temperatures in `white`.
+fn parse_pin_lights(yaml: &Yaml, layout: &Layout) -> Vec<(LightId, PinLight)> {
+    yaml.as_hash()
+        .expect("Expected a map of lights")
+        .iter()
+        .map(|entry| {
+            let light_id = find_light(layout, entry.0.as_str().unwrap()).id.clone();
+            let pin_map = entry.1;
+            let light = PinLight::new(
+                pin_map["r"].as_i64().unwrap(),
+                pin_map["g"].as_i64().unwrap(),
+                pin_map["b"].as_i64().unwrap(),
+            );
+            let light = match (
+                pin_map["w"].as_i64(),
+                pin_map["ww"].as_i64(),
+                pin_map["cw"].as_i64(),
+            ) {
+                (Some(w), None, None) => light.with_white(
+                    vec![w],
+                    parse_whites(&pin_map["white"], WhiteLeds::default()),
+                ),
+                (None, Some(ww), Some(cw)) => light.with_white(
+                    vec![ww, cw],
+                    parse_whites(&pin_map["white"], WhiteLeds::tunable(2700., 6500.)),
+                ),
+                (None, None, None) => light,
+                _ => panic!("Expected either a w pin or ww and cw pins"),
+            };
+            (light_id, light)
+        })
+        .collect()
+}
+
+/// Reads a fixture profile.  Example:
+///
+/// ```yaml
+/// channels: [pan, pan-fine, tilt, tilt-fine, 0, dimmer, strobe, red, green, blue]
+/// strobe: {off: 0, slow: 10, fast: 255}
+/// white: 6500
+/// ```
+///
+/// Numbers are channels that always have that value.
+fn parse_profile(yaml: &Yaml) -> Result<Profile, String> {
+    let channels = yaml["channels"]
+        .as_vec()
+        .ok_or("A profile needs channels")?
+        .iter()
+        .map(|channel| match channel {
+            Yaml::String(name) => name.parse(),
+            Yaml::Integer(value) if (0..256).contains(value) => Ok(Channel::Fixed(*value as u8)),
+            other => Err(format!("Invalid channel: {:?}", other)),
+        })
+        .collect::<Result<Vec<Channel>, String>>()?;
+    let mut profile = Profile::new(channels);
+    let strobe = &yaml["strobe"];
+    let strobe_value = |key: &str, default: u8| strobe[key].as_i64().map_or(default, |v| v as u8);
+    profile.strobe = [
+        strobe_value("off", profile.strobe[0]),
+        strobe_value("slow", profile.strobe[1]),
+        strobe_value("fast", profile.strobe[2]),
+    ];
+    profile.whites = parse_whites(&yaml["white"], profile.whites);
+    Ok(profile)
+}
+
+/// Reads all `.yaml` files in the directory as fixture profiles, named
+/// like the file without the extension.
+fn load_profiles(dir: &Path) -> HashMap<String, Profile> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => panic!("Failed to read fixture profiles in {:?}: {}", dir, e),
+    };
+    let mut profiles = HashMap::new();
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("yaml") {
+            continue;
+        }
+        let name = path.file_stem().unwrap().to_string_lossy().to_string();
+        let yaml_str = std::fs::read_to_string(&path).expect("Error reading fixture profile.");
+        let docs = yaml_rust::YamlLoader::load_from_str(&yaml_str)
+            .unwrap_or_else(|e| panic!("Error parsing {:?}: {}", path, e));
+        match parse_profile(&docs[0]) {
+            Ok(profile) => profiles.insert(name, profile),
+            Err(e) => panic!("{:?}: {}", path, e),
+        };
+    }
+    profiles
+}
+
+/// Reads the DMX address of each light.  `channel` counts from 1,
+/// `profile` is a built in layout, `rgb` (the default), `rgbw`,
+/// `rgbww`, `cct`, `dimmer-cct` or `dimmer-rgb`, or one from the
+/// `fixtures` directory.  `white` is the color temperature of the white
+/// LEDs, `pan` and `tilt` the part of the range a moving head uses.
+/// The pixels of a strip use consecutive channels.
+fn parse_dmx_lights(
+    yaml: &Yaml,
+    layout: &Layout,
+    profiles: &HashMap<String, Profile>,
+) -> DmxMapping {
+    DmxMapping::new(
+        yaml.as_hash()
+            .expect("Expected a map of lights")
+            .iter()
+            .map(|(id, light)| {
+                let layout_light = find_light(layout, id.as_str().unwrap());
+                // `layout` is the older name
+                let name = light["profile"]
+                    .as_str()
+                    .or_else(|| light["layout"].as_str())
+                    .unwrap_or("rgb");
+                let profile = match profiles.get(name) {
+                    Some(profile) => profile.clone(),
+                    None => Profile::builtin(name)
+                        .unwrap_or_else(|| panic!("Unknown fixture profile: {}", name)),
+                };
+                let whites = parse_whites(&light["white"], profile.whites);
+                if profile.is_tunable() && matches!(whites, WhiteLeds::Single(_)) {
+                    panic!(
+                        "{} has a warm and a cold white, `white` needs both",
+                        id.as_str().unwrap()
+                    );
+                }
+                let range = |key: &str| match &light[key] {
+                    Yaml::BadValue => (0., 1.),
+                    range => {
+                        let range = yaml_to_coordinate(range);
+                        (range.0, range.1)
+                    }
+                };
+                (
+                    layout_light.id.clone(),
+                    DmxLight {
+                        universe: light["universe"].as_i64().unwrap_or(0) as u16,
+                        channel: light["channel"].as_i64().expect("Light without channel")
+                            as usize,
+                        pixels: layout_light.pixels.len(),
+                        profile,
+                        whites,
+                        aim: Aim {
+                            pan: range("pan"),
+                            tilt: range("tilt"),
+                        },
+                    },
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Reads the LED range of each light, `start` counts from 0.  `count`
+/// defaults to the number of pixels of the light.
+fn parse_pixel_lights(yaml: &Yaml, layout: &Layout) -> PixelMapping {
+    PixelMapping::new(
+        yaml.as_hash()
+            .expect("Expected a map of lights")
+            .iter()
+            .map(|(id, light)| {
+                let layout_light = find_light(layout, id.as_str().unwrap());
+                (
+                    layout_light.id.clone(),
+                    PixelLight {
+                        start: light["start"].as_i64().unwrap_or(0) as usize,
+                        count: light["count"]
+                            .as_i64()
+                            .map(|c| c as usize)
+                            .unwrap_or_else(|| layout_light.pixels.len()),
+                    },
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Reads a brightness curve: `linear`, `cie`, `gamma` (2.2) or
+/// `{gamma: 2.8}`, or points to interpolate between:
+///
+/// ```yaml
+/// curve:
+///   points: [[0, 0], [0.5, 0.15], [1, 1]]
+///   smooth: true
+/// ```
+///
+/// None if no curve is given.
+fn parse_curve(yaml: &Yaml) -> Option<Curve> {
+    match yaml {
+        Yaml::BadValue => None,
+        Yaml::String(name) => match name.as_str() {
+            "linear" => Some(Curve::Linear),
+            "cie" => Some(Curve::Cie),
+            "gamma" => Some(Curve::Gamma(2.2)),
+            other => panic!("Unknown curve: {}", other),
+        },
+        _ if !yaml["gamma"].is_badvalue() => {
+            Some(Curve::Gamma(yaml_to_f32(&yaml["gamma"], 2.2) as PinValue))
+        }
+        _ => {
+            let points = yaml["points"]
+                .as_vec()
+                .expect("A curve needs a name, a gamma or points")
+                .iter()
+                .map(|p| {
+                    let p = yaml_to_coordinate(p);
+                    (p.0, p.1)
+                })
+                .collect();
+            let smooth = yaml["smooth"].as_bool().unwrap_or(false);
+            Some(Curve::spline(points, smooth).unwrap())
+        }
+    }
+}
+
+/// Reads the source name, CID and priority of an sACN output.  A
+/// random CID is made up if none is given.
+fn parse_sacn_source(yaml: &Yaml) -> SacnSource {
+    let cid = match yaml["cid"].as_str() {
+        Some(cid) => parse_cid(cid).unwrap(),
+        None => {
+            let cid: [u8; 16] = rand::random();
+            let hex: String = cid.iter().map(|b| format!("{:02x}", b)).collect();
+            log::warn!("No sACN cid configured, using {} for now.", hex);
+            cid
+        }
+    };
+    SacnSource {
+        name: yaml["name"].as_str().unwrap_or("nightfire").to_string(),
+        cid,
+        priority: yaml["priority"].as_i64().unwrap_or(100).clamp(0, 200) as u8,
     }
 }
 
+/// Logs why an output could not be opened, the others still work.
+fn check_output<T: OutputBackend + 'static>(
+    output: Result<T, String>,
+) -> Option<Box<dyn OutputBackend>> {
+    match output {
+        Ok(output) => Some(Box::new(output)),
+        Err(e) => {
+            log::error!("Output not available: {}", e);
+            None
+        }
+    }
+}
+
+fn open_pi_blaster(path: &str, lights: &Yaml, layout: &Layout) -> Option<Box<dyn OutputBackend>> {
+    if cfg!(feature = "pi-blaster") {
+        check_output(pb::Lights::new(parse_pin_lights(lights, layout), path))
+    } else {
+        log::warn!("Built without pi-blaster support, output ignored.");
+        None
+    }
+}
+
+/// Opens a single output, None if it is not available in this build.
+fn parse_output(
+    yaml: &Yaml,
+    layout: &Layout,
+    profiles: &HashMap<String, Profile>,
+) -> Option<Box<dyn OutputBackend>> {
+    match yaml["type"].as_str().expect("Output without type") {
+        "pi-blaster" => open_pi_blaster(
+            yaml["path"].as_str().unwrap_or("/dev/pi-blaster"),
+            &yaml["lights"],
+            layout,
+        ),
+        "artnet" => {
+            let artnet = ArtNet::new(
+                yaml["address"].as_str().unwrap_or("255.255.255.255"),
+                parse_dmx_lights(&yaml["lights"], layout, profiles),
+            );
+            if let (Ok(artnet), Some(true)) = (&artnet, yaml["discover"].as_bool()) {
+                artnet.discover();
+            }
+            check_output(artnet)
+        }
+        "sacn" => check_output(Sacn::new(
+            yaml["address"].as_str(),
+            parse_sacn_source(yaml),
+            parse_dmx_lights(&yaml["lights"], layout, profiles),
+        )),
+        "opc" => check_output(Opc::new(
+            &yaml["address"]
+                .as_str()
+                .map(|a| a.to_string())
+                .unwrap_or(format!("127.0.0.1:{}", OPC_PORT)),
+            yaml["channel"].as_i64().unwrap_or(0) as u8,
+            parse_pixel_lights(&yaml["lights"], layout),
+        )),
+        "ddp" => check_output(Ddp::new(
+            yaml["address"].as_str().expect("DDP output without address"),
+            parse_pixel_lights(&yaml["lights"], layout),
+        )),
+        "serial" => Some(Box::new(Serial::new(
+            yaml["path"].as_str().expect("Serial output without path"),
+            yaml["baud-rate"].as_i64().unwrap_or(115_200) as u32,
+            yaml["framing"].as_str().unwrap_or("adalight").parse().unwrap(),
+            yaml["color-order"].as_str().unwrap_or("rgb").parse().unwrap(),
+            parse_pixel_lights(&yaml["lights"], layout),
+        ))),
+        "pwm" => check_output(SysfsPwm::new(
+            Path::new(yaml["sysfs"].as_str().unwrap_or(SYSFS_PWM)),
+            yaml["chip"].as_i64().unwrap_or(0) as u32,
+            yaml["period"].as_i64().unwrap_or(1_000_000) as u64,
+            yaml["resolution"].as_i64().unwrap_or(1000) as u32,
+            parse_pin_lights(&yaml["lights"], layout),
+        )),
+        "gpio" => check_output(GpioPwm::new(
+            yaml["chip"].as_str().unwrap_or("/dev/gpiochip0"),
+            Duration::from_nanos(yaml["period"].as_i64().unwrap_or(10_000_000) as u64),
+            yaml["resolution"].as_i64().unwrap_or(32) as u32,
+            parse_pin_lights(&yaml["lights"], layout),
+        )),
+        other => panic!("Unknown output type: {}", other),
+    }
+}
+
+/// Reads the outputs, several can be used at the same time.  Example:
+///
+/// ```yaml
+/// outputs:
+///   - type: pi-blaster
+///     path: /dev/pi-blaster
+///     lights:
+///       Top: {r: 14, g: 15, b: 18}
+///   - type: artnet
+///     address: 2.255.255.255
+///     lights:
+///       Top: {universe: 0, channel: 1, layout: rgbw}
+///   - type: sacn
+///     name: nightfire
+///     priority: 100
+///     lights:
+///       Top: {universe: 1, channel: 1}
+///   - type: ddp
+///     address: 192.168.1.60
+///     max-fps: 40
+///     lights:
+///       Top: {start: 0, count: 30}
+/// ```
+///
+/// Every output can have its own `curve`, the top level one is the
+/// default, and `dither: true` to dither to the steps of the output.
+/// Outputs are sent at the frame rate, or `fps` times per second.
+/// `max-fps` is the same, but doesn't make the output loop faster, so
+/// it only ever slows an output down; an output has one or the other.
+///
+/// The top level `pi-blaster` and `lights` keys of older configs
+/// become a single pi-blaster output.
+fn parse_outputs(
+    conf: &Yaml,
+    layout: &Layout,
+    curve: &Curve,
+    profiles: &HashMap<String, Profile>,
+    timing: &FrameTiming,
+) -> Vec<Box<dyn OutputBackend>> {
+    let with_transfer = |yaml: &Yaml, backend| -> Box<dyn OutputBackend> {
+        let transfer = Box::new(Transfer::new(
+            backend,
+            parse_curve(&yaml["curve"]).unwrap_or_else(|| curve.clone()),
+            layout,
+            yaml["dither"].as_bool().unwrap_or(false),
+        ));
+        let fps = match (&yaml["fps"], &yaml["max-fps"]) {
+            (Yaml::BadValue, Yaml::BadValue) => timing.fps,
+            (fps, Yaml::BadValue) | (Yaml::BadValue, fps) => yaml_to_f32(fps, 0.) as f64,
+            _ => panic!("An output has either fps or max-fps, not both"),
+        };
+        if fps <= 0. {
+            panic!("The fps of an output must be above 0");
+        }
+        if fps < timing.tick_fps {
+            let name = yaml["type"].as_str().unwrap_or("pi-blaster");
+            let name = match yaml["address"].as_str().or_else(|| yaml["path"].as_str()) {
+                Some(at) => format!("Output {} {}", name, at),
+                None => format!("Output {}", name),
+            };
+            Box::new(Paced::new(transfer, &name, fps, timing))
+        } else {
+            transfer
+        }
+    };
+    let mut outputs: Vec<Box<dyn OutputBackend>> = conf["outputs"]
+        .as_vec()
+        .map(|list| {
+            list.iter()
+                .filter_map(|o| parse_output(o, layout, profiles).map(|b| with_transfer(o, b)))
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(path) = conf["pi-blaster"].as_str() {
+        let backend = open_pi_blaster(path, &conf["lights"], layout);
+        outputs.extend(backend.map(|b| with_transfer(&Yaml::BadValue, b)));
+    }
+    if outputs.is_empty() {
+        log::warn!("No outputs configured!");
+    }
+    outputs
+}
+
+/// Reads how often frames are computed and sent.  The output loop runs
+/// as fast as the fastest output, with `interpolate` the frames are
+/// blended on the ticks in between.  Example:
+///
+/// ```yaml
+/// frame-rate: 50
+/// interpolate: true
+/// timing-stats: 10
+/// outputs:
+///   - type: pwm
+///     fps: 200
+/// ```
+fn parse_timing(conf: &Yaml) -> FrameTiming {
+    let fps = yaml_to_f32(&conf["frame-rate"], 50.) as f64;
+    if fps <= 0. {
+        panic!("The frame rate must be above 0");
+    }
+    let tick_fps = conf["outputs"]
+        .as_vec()
+        .map(|list| {
+            list.iter()
+                .map(|o| yaml_to_f32(&o["fps"], 0.) as f64)
+                .fold(fps, f64::max)
+        })
+        .unwrap_or(fps);
+    FrameTiming {
+        fps,
+        tick_fps,
+        interpolate: conf["interpolate"].as_bool().unwrap_or(false),
+        stats: match &conf["timing-stats"] {
+            Yaml::BadValue => None,
+            secs => {
+                let secs = yaml_to_f32(secs, 0.);
+                if secs <= 0. {
+                    panic!("The timing-stats interval must be above 0 seconds");
+                }
+                Some(Duration::from_secs_f32(secs))
+            }
+        },
+    }
+}
+
+/// A single current for all LEDs, or one for each of r, g, b and the
+/// white or the warm and cold white.  Whites that aren't given draw as
+/// much as the highest of the others.
+fn yaml_to_current(yaml: &Yaml, default: f64) -> Current {
+    match yaml {
+        Yaml::Array(list) if (3..=5).contains(&list.len()) => {
+            let given: Vec<f64> = list
+                .iter()
+                .map(|c| yaml_to_f32(c, default as f32) as f64)
+                .collect();
+            let mut current = [given.iter().cloned().fold(0., f64::max); 5];
+            current[..given.len()].copy_from_slice(&given);
+            current
+        }
+        Yaml::Array(_) => panic!("Expected the currents of r, g, b and the whites"),
+        _ => [yaml_to_f32(yaml, default as f32) as f64; 5],
+    }
+}
+
+/// Wraps the outputs in a power limiter if there is a `power` section.
+/// The currents are in mA per LED of a pixel.  Example:
+///
+/// ```yaml
+/// power:
+///   budget: 10000
+///   current: 20
+///   lights:
+///     Top: 350
+///     Strip: [15, 15, 20]
+///     Bar: [15, 15, 20, 25]
+///   release: 1
+/// ```
+fn parse_power(
+    yaml: &Yaml,
+    outputs: Vec<Box<dyn OutputBackend>>,
+    layout: &Layout,
+) -> Vec<Box<dyn OutputBackend>> {
+    if yaml.is_badvalue() {
+        return outputs;
+    }
+    let budget = match &yaml["budget"] {
+        Yaml::BadValue => panic!("The power section needs a budget in mA"),
+        budget => yaml_to_f32(budget, 0.) as f64,
+    };
+    let currents = yaml["lights"]
+        .as_hash()
+        .map(|lights| {
+            lights
+                .iter()
+                .map(|(name, current)| {
+                    let light = find_light(layout, name.as_str().unwrap());
+                    (light.id.clone(), yaml_to_current(current, 0.))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    vec![Box::new(PowerLimiter::new(
+        outputs,
+        layout,
+        budget,
+        yaml_to_current(&yaml["current"], 20.),
+        currents,
+        yaml_to_f32(&yaml["release"], 1.) as f64,
+    ))]
+}
+
 impl Conf {
     pub fn new() -> Conf {
         let conf_path = Conf::find_path().expect("Config file could not be found!");
@@ -26,42 +866,67 @@ impl Conf {
         let docs =
             yaml_rust::YamlLoader::load_from_str(&yaml_str).expect("Error parsing config file.");
         let conf = &docs[0];
-        let pi_blaster_path = conf["pi-blaster"].as_str().unwrap().to_string();
-        let lights = conf["lights"]
-            .as_hash()
-            .unwrap()
-            .iter()
-            .map(|entry| {
-                let light_id = str_to_light_id(entry.0.as_str().unwrap());
-                let pin_map = entry.1;
-                (
-                    light_id,
-                    pb::Light::new(
-                        pin_map["r"].as_i64().unwrap(),
-                        pin_map["g"].as_i64().unwrap(),
-                        pin_map["b"].as_i64().unwrap(),
-                    ),
-                )
-            })
-            .collect();
-        let lights = pb::Lights::new(lights, &pi_blaster_path);
-        let audio_in = conf["audio-in"].as_str();
-        let audio_params = match audio_in {
-            Some(s) => {
-                if s == "off" {
-                    None
-                } else {
-                    Some(s.to_string())
+        let layout = parse_layout(&conf["layout"]);
+        let curve = parse_curve(&conf["curve"]).unwrap_or_default();
+        let conf_dir = conf_path.parent().unwrap_or_else(|| Path::new("."));
+        let profiles = conf["fixtures"]
+            .as_str()
+            .map(|dir| load_profiles(&conf_dir.join(dir)))
+            .unwrap_or_default();
+        let timing = parse_timing(conf);
+        let outputs = parse_power(
+            &conf["power"],
+            parse_outputs(conf, &layout, &curve, &profiles, &timing),
+            &layout,
+        );
+        // audio-in and audio-in-backup can both be lists, all sources
+        // are tried in order
+        let mut audio_params = yaml_to_specs(&conf["audio-in"]);
+        if audio_params.is_empty() {
+            log::warn!("No audio in port specified in config! Audio processing turned off.");
+        } else if audio_params[0] == "off" {
+            audio_params.clear();
+        } else {
+            audio_params.extend(yaml_to_specs(&conf["audio-in-backup"]));
+        }
+        let audio_channel = match &conf["audio-channel"] {
+            Yaml::Integer(i) => ChannelSelection::Channel(*i as usize),
+            Yaml::String(s) => s.parse().unwrap(),
+            _ => ChannelSelection::default(),
+        };
+        let audio_sample_rate = conf["audio-sample-rate"]
+            .as_i64()
+            .map(|rate| rate as f32);
+        let audio_timeout = match &conf["audio-timeout"] {
+            Yaml::String(s) if s == "off" => None,
+            Yaml::BadValue => Some(FRAME_TIMEOUT),
+            secs => {
+                let secs = yaml_to_f32(secs, 0.);
+                if secs <= 0. {
+                    panic!("The audio-timeout must be above 0 seconds, or off");
                 }
-            }
-            None => {
-                log::warn!("No audio in port specified in config! Audio processing turned off.");
-                None
+                Some(Duration::from_secs_f32(secs))
             }
         };
+        let preprocessing = parse_preprocessing(&conf["preprocessing"]);
+        let recorder = parse_recorder(&conf["recorder"]);
+        let osc_port = conf["osc-port"].as_i64().map(|port| port as u16);
+        let text = conf["text"].as_str().map(|text| text.to_string());
+        let luts = parse_luts(conf, conf_dir);
         Conf {
-            lights: lights,
+            layout,
+            outputs,
+            curve,
             audio_in: audio_params,
+            audio_channel: audio_channel,
+            audio_sample_rate: audio_sample_rate,
+            audio_timeout,
+            preprocessing: preprocessing,
+            recorder,
+            osc_port,
+            text,
+            luts,
+            timing,
         }
     }
 
@@ -0,0 +1,296 @@
+//! The lights of an installation, as defined in the config.  Every
+//! light has a name and a shape in the coordinate space that the color
+//! maps are sampled in.  A single lamp is a point, a pixel strip has
+//! its pixels spread along a path, and a matrix has its pixels on a
+//! grid.
+use crate::light::coord::distance;
+use crate::light::Coordinate;
+use crate::output::calibration::Calibration;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct LightId(pub String);
+
+impl LightId {
+    pub fn get(s: &str) -> Self {
+        Self(s.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub enum Shape {
+    Point(Coordinate),
+    /// A line through the points, with the pixels evenly spaced from
+    /// the first to the last point.
+    Path(Vec<Coordinate>),
+    /// Part of a circle.  The angles are in degrees, clockwise with 0
+    /// at the top; a full circle goes from 0 to 360.
+    Arc {
+        center: Coordinate,
+        radius: f64,
+        from: f64,
+        to: f64,
+    },
+}
+
+impl Shape {
+    /// The positions of n pixels along the shape.
+    pub fn sample(&self, n: usize) -> Vec<Coordinate> {
+        match self {
+            Shape::Point(pos) => vec![*pos; n],
+            Shape::Path(points) => sample_path(points, n),
+            Shape::Arc {
+                center,
+                radius,
+                from,
+                to,
+            } => {
+                // on a full circle the last pixel is not on the first
+                let full_circle = (to - from).abs() >= 360.;
+                let steps = if full_circle { n } else { n.max(2) - 1 };
+                (0..n)
+                    .map(|i| {
+                        let angle = (from + (to - from) * i as f64 / steps as f64) * PI / 180.;
+                        Coordinate(
+                            center.0 + radius * angle.sin(),
+                            center.1 + radius * angle.cos(),
+                        )
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A path needs at least one point, the config doesn't allow empty
+/// ones.
+fn sample_path(points: &[Coordinate], n: usize) -> Vec<Coordinate> {
+    assert!(!points.is_empty(), "Empty path");
+    if points.len() == 1 || n < 2 {
+        return vec![points[0]; n];
+    }
+    let lengths: Vec<f64> = points.windows(2).map(|w| distance(&w[0], &w[1])).collect();
+    let total: f64 = lengths.iter().sum();
+    (0..n)
+        .map(|i| {
+            // walk along the segments until the distance is used up
+            let mut rest = total * i as f64 / (n - 1) as f64;
+            for (segment, length) in points.windows(2).zip(&lengths) {
+                if rest <= *length && *length > 0. {
+                    let t = rest / length;
+                    return Coordinate(
+                        segment[0].0 + (segment[1].0 - segment[0].0) * t,
+                        segment[0].1 + (segment[1].1 - segment[0].1) * t,
+                    );
+                }
+                rest -= length;
+            }
+            points[points.len() - 1]
+        })
+        .collect()
+}
+
+/// How the LEDs of a matrix panel are chained.  Both start at the top
+/// left of the panel and run along the rows.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wiring {
+    /// Every row starts on the left.
+    Progressive,
+    /// Every other row runs backwards, the usual zig-zag of panels
+    /// made from a single strip.
+    Serpentine,
+}
+
+impl FromStr for Wiring {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "progressive" => Ok(Wiring::Progressive),
+            "serpentine" => Ok(Wiring::Serpentine),
+            other => Err(format!("Unknown wiring: {}", other)),
+        }
+    }
+}
+
+/// A grid of width × height pixels, covering a rectangle of `size`
+/// around `center`.  The width and height are as seen, after the
+/// rotation.
+#[derive(Copy, Clone)]
+pub struct Matrix {
+    pub width: usize,
+    pub height: usize,
+    pub wiring: Wiring,
+    /// How far the panel is turned clockwise, 0, 90, 180 or 270 degrees.
+    pub rotation: u16,
+    pub center: Coordinate,
+    pub size: (f64, f64),
+}
+
+impl Matrix {
+    pub fn n_pixels(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// The image position of the n-th LED, x to the right and y down
+    /// from the top left.
+    pub fn pixel(&self, n: usize) -> (usize, usize) {
+        // the size of the panel before it was turned
+        let (panel_w, panel_h) = match self.rotation {
+            90 | 270 => (self.height, self.width),
+            _ => (self.width, self.height),
+        };
+        let row = n / panel_w;
+        let col = match self.wiring {
+            Wiring::Serpentine if row % 2 == 1 => panel_w - 1 - n % panel_w,
+            _ => n % panel_w,
+        };
+        match self.rotation {
+            90 => (panel_h - 1 - row, col),
+            180 => (panel_w - 1 - col, panel_h - 1 - row),
+            270 => (row, panel_w - 1 - col),
+            _ => (col, row),
+        }
+    }
+
+    /// The positions of all LEDs, in the order they are wired.
+    pub fn positions(&self) -> Vec<Coordinate> {
+        (0..self.n_pixels())
+            .map(|n| {
+                let (x, y) = self.pixel(n);
+                Coordinate(
+                    self.center.0 + ((x as f64 + 0.5) / self.width as f64 - 0.5) * self.size.0,
+                    self.center.1 - ((y as f64 + 0.5) / self.height as f64 - 0.5) * self.size.1,
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone)]
+pub struct Light {
+    pub id: LightId,
+    /// The position of every pixel, a single one for a lamp.  The
+    /// pixels are in the order they are wired.
+    pub pixels: Vec<Coordinate>,
+    /// Free form groups, like `ceiling` or `stage-left`.
+    pub tags: Vec<String>,
+    /// Set if the pixels form a grid, so modes can draw images on it.
+    pub matrix: Option<Matrix>,
+    pub calibration: Calibration,
+}
+
+impl Light {
+    pub fn new(name: &str, shape: Shape, n_pixels: usize) -> Light {
+        Light {
+            id: LightId::get(name),
+            pixels: shape.sample(n_pixels.max(1)),
+            tags: vec![],
+            matrix: None,
+            calibration: Calibration::default(),
+        }
+    }
+
+    pub fn matrix(name: &str, matrix: Matrix) -> Light {
+        if ![0, 90, 180, 270].contains(&matrix.rotation) {
+            panic!("Rotation of {} must be 0, 90, 180 or 270", name);
+        }
+        if matrix.n_pixels() == 0 {
+            panic!("Matrix {} has no pixels", name);
+        }
+        Light {
+            id: LightId::get(name),
+            pixels: matrix.positions(),
+            tags: vec![],
+            matrix: Some(matrix),
+            calibration: Calibration::default(),
+        }
+    }
+
+    /// A light with a single pixel.
+    pub fn point(name: &str, pos: Coordinate) -> Light {
+        Light::new(name, Shape::Point(pos), 1)
+    }
+
+    /// The mean of the pixel positions.
+    pub fn center(&self) -> Coordinate {
+        let n = self.pixels.len() as f64;
+        Coordinate(
+            self.pixels.iter().map(|p| p.0).sum::<f64>() / n,
+            self.pixels.iter().map(|p| p.1).sum::<f64>() / n,
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct Layout {
+    lights: Vec<Light>,
+}
+
+impl Default for Layout {
+    /// The four lights of the original setup, one in each quadrant.
+    fn default() -> Self {
+        Layout::new(vec![
+            Light::point("Top", Coordinate(-1.0, 1.0)),
+            Light::point("Bottom", Coordinate(1.0, -1.0)),
+            Light::point("Left", Coordinate(-1.0, -1.0)),
+            Light::point("Right", Coordinate(1.0, 1.0)),
+        ])
+    }
+}
+
+impl Layout {
+    pub fn new(lights: Vec<Light>) -> Layout {
+        Layout { lights }
+    }
+
+    /// All lights, in the order of the config.
+    pub fn all(&self) -> &[Light] {
+        &self.lights
+    }
+
+    pub fn get(&self, id: &LightId) -> Option<&Light> {
+        self.lights.iter().find(|l| &l.id == id)
+    }
+
+    /// The lights with the given tag.
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Light> {
+        self.lights.iter().filter(move |l| l.tags.iter().any(|t| t == tag))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xy(pixels: Vec<Coordinate>) -> Vec<(f64, f64)> {
+        pixels.iter().map(|c| (c.0, c.1)).collect()
+    }
+
+    #[test]
+    fn path_spacing() {
+        let path = Shape::Path(vec![
+            Coordinate(0., 0.),
+            Coordinate(1., 0.),
+            Coordinate(1., 1.),
+        ]);
+        let expected = [(0., 0.), (0.5, 0.), (1., 0.), (1., 0.5), (1., 1.)];
+        assert_eq!(xy(path.sample(5)), expected);
+    }
+
+    #[test]
+    fn path_without_length() {
+        let point = Shape::Path(vec![Coordinate(0.5, 0.5)]);
+        assert_eq!(xy(point.sample(3)), [(0.5, 0.5); 3]);
+        let same = Shape::Path(vec![Coordinate(0.5, 0.5); 2]);
+        assert_eq!(xy(same.sample(3)), [(0.5, 0.5); 3]);
+        assert_eq!(xy(same.sample(0)), []);
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_path() {
+        Shape::Path(vec![]).sample(3);
+    }
+}
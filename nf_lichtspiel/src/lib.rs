@@ -1,9 +1,10 @@
 pub mod conf;
 pub mod inactivity;
+pub mod layout;
 pub mod light;
-pub mod lightid;
 pub mod mode;
+pub mod osc;
+pub mod output;
 pub mod periodic_updater;
-pub mod piblaster;
 pub mod ui;
 pub mod util;
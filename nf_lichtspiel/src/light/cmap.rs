@@ -4,6 +4,29 @@ use crate::light::ColorsExt;
 /// A color map.  Maps any given coordinate to a color.
 pub trait ColorMap {
     fn get_color(&self, pos: &li::Coordinate) -> li::Color;
+
+    /// Draws straight into the image of a matrix light.  Returns false
+    /// if the map has no image of its own, then the pixels of the
+    /// matrix are sampled with get_color instead.
+    fn render_image(&self, _image: &mut li::Image) -> bool {
+        false
+    }
+
+    /// What the map says besides the colors, for fixtures that have
+    /// channels for it.
+    fn parameters(&self) -> Parameters {
+        Parameters::default()
+    }
+}
+
+/// Values besides the colors, like where moving heads point to.
+#[derive(Copy, Clone, Default)]
+pub struct Parameters {
+    /// Where moving heads point to, in the coordinates of the layout.
+    /// They go to the middle of their range if it is None.
+    pub position: Option<li::Coordinate>,
+    /// The strobe speed, 0 is off and 1 the fastest.
+    pub strobe: li::PinValue,
 }
 
 /// A static color map.  Is created with a single color and keeps that
@@ -93,6 +116,13 @@ impl ManualMode {
         self.br_color = color;
     }
 
+    pub fn shift_hue(&mut self, degrees: f64) {
+        self.tl_color = self.tl_color.shift_hue(degrees);
+        self.tr_color = self.tr_color.shift_hue(degrees);
+        self.bl_color = self.bl_color.shift_hue(degrees);
+        self.br_color = self.br_color.shift_hue(degrees);
+    }
+
     pub fn rotate_cw(&mut self) {
         let temp = self.tl_color;
         self.tl_color = self.bl_color;
@@ -103,6 +103,35 @@ pub trait ColorsExt {
         Color::new(1.0, 0.1, 0.7)
     }
 
+    /// White of the given color temperature in Kelvin, 2700 is warm
+    /// like a light bulb, 6500 daylight.  The brightest channel is 1.
+    /// Outputs with white LEDs make it with those.
+    fn kelvin(kelvin: f64) -> Color {
+        // the approximation of the black body colors by Tanner Helland
+        let t = kelvin.clamp(1000., 40000.) / 100.;
+        let r = if t <= 66. {
+            255.
+        } else {
+            329.698727446 * (t - 60.).powf(-0.1332047592)
+        };
+        let g = if t <= 66. {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.).powf(-0.0755148492)
+        };
+        let b = if t >= 66. {
+            255.
+        } else if t <= 19. {
+            0.
+        } else {
+            138.5177312231 * (t - 10.).ln() - 305.0447927307
+        };
+        let to_unit = |v: f64| v.clamp(0., 255.) / 255.;
+        let color = Rgb::<Srgb, PinValue>::new(to_unit(r), to_unit(g), to_unit(b)).into_linear();
+        let max = color.red.max(color.green).max(color.blue);
+        Color::new(color.red / max, color.green / max, color.blue / max)
+    }
+
     fn random() -> Color {
         let x = rand::random::<f64>();
         let hue = RgbHue::from(x * 360. - 180.);
@@ -118,6 +147,13 @@ pub trait ColorsExt {
         Color::from(Hsv::new(hue, hsv.saturation, hsv.value))
     }
 
+    /// The same color with the hue turned by `degrees`.
+    fn shift_hue(&self, degrees: f64) -> Color {
+        let hsv = self._self().into_hsv::<Srgb>();
+        let hue = RgbHue::from(hsv.hue.to_positive_degrees() + degrees);
+        Color::from(Hsv::new(hue, hsv.saturation, hsv.value))
+    }
+
     fn mask(&self, mut value: f64) -> Color {
         if value > 1. {
             value = 1.;
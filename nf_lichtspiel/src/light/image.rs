@@ -0,0 +1,95 @@
+use crate::light::color::{Color, ColorsExt};
+use crate::light::coord::Coordinate;
+use crate::light::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+/// A buffer of pixels that modes can draw into, for matrix lights.
+/// x goes to the right and y down from the top left pixel.
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    /// A black image.
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![Color::black(); width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Sets a pixel, pixels outside of the image are ignored, so
+    /// shapes can be drawn partly off the image.
+    pub fn set(&mut self, x: i64, y: i64, color: Color) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    /// Replaces every pixel with what f makes of it.
+    pub fn map_colors<F: Fn(&Color) -> Color>(&mut self, f: F) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = f(pixel);
+        }
+    }
+
+    /// The pixel at a coordinate, with the image stretched over the
+    /// whole coordinate space.
+    pub fn sample(&self, pos: &Coordinate) -> Color {
+        let to_pixel = |v: f64, n: usize| (((v + 1.) / 2. * n as f64) as usize).min(n - 1);
+        self.get(to_pixel(pos.0, self.width), to_pixel(-pos.1, self.height))
+    }
+
+    /// Draws text with its top left corner at x, y, every font pixel
+    /// `scale` pixels big.
+    pub fn draw_text(&mut self, text: &str, x: i64, y: i64, scale: usize, color: Color) {
+        let scale = scale as i64;
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i64 * text_advance(scale as usize) as i64;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                        continue;
+                    }
+                    for dy in 0..scale {
+                        for dx in 0..scale {
+                            let px = left + col as i64 * scale + dx;
+                            self.set(px, y + row as i64 * scale + dy, color);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// How far the text moves on per character, with one pixel of space.
+pub fn text_advance(scale: usize) -> usize {
+    (GLYPH_WIDTH + 1) * scale
+}
+
+/// The largest font scale that fits the height, at least 1.
+pub fn text_scale(height: usize) -> usize {
+    (height / (GLYPH_HEIGHT + 1)).max(1)
+}
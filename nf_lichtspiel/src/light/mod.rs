@@ -4,6 +4,8 @@ pub mod envelope;
 pub mod cmap;
 pub mod mask;
 pub mod layer;
+pub mod font;
+pub mod image;
 mod state;
 mod cprov;
 
@@ -13,6 +15,7 @@ pub use color::PinValue;
 pub use coord::Coordinate;
 pub use coord::Quadrant;
 pub use coord::hue_from_angle;
+pub use image::Image;
 pub use state::Mode;
 pub use state::State;
 pub use mask::Mask;
@@ -8,6 +8,9 @@ use dualshock3::Controller;
 use nightfire::audio::{intensity::IntensityID, AudioEvent2, EdgeID, SignalProcessor};
 use pi_ir_remote::Signal;
 
+/// Degrees the hue turns per frame at full harmonic intensity.
+const HUE_DRIFT: f64 = 0.5;
+
 pub struct AutoMode {
     base_layer: Layer<ManualMode, SolidMask>,
     change_all: bool,
@@ -71,13 +74,13 @@ impl Mode for AutoMode {
         }
     }
 
-    fn audio_update(&mut self, frame: &[f32]) {
+    fn audio_update(&mut self, frame: &[f32]) -> Vec<AudioEvent2> {
         let events = self.signal_processor.add_audio_frame(frame);
         // if we get a significant onset score, we flash
-        for event in events {
+        for event in events.iter() {
             match event {
                 AudioEvent2::Onset(edge_id) => {
-                    if edge_id == EdgeID::get("bass") {
+                    if *edge_id == EdgeID::get("bass") {
                         self.flash_layer.mask.reset_bottom();
                         if self.change_all {
                             self.base_layer
@@ -88,7 +91,7 @@ impl Mode for AutoMode {
                             self.base_layer.map.set_color(Quadrant::random(), c);
                             self.base_layer.map.set_color(Quadrant::random(), c);
                         }
-                    } else if edge_id == EdgeID::get("highs") {
+                    } else if *edge_id == EdgeID::get("highs") {
                         self.flash_layer.mask.reset_top();
                         if self.change_all {
                             self.base_layer
@@ -102,7 +105,11 @@ impl Mode for AutoMode {
                     }
                 }
                 AudioEvent2::Intensities(intensities) => {
-                    if let Some(bass_intensity) = intensities.get(&IntensityID::get("bass")) {
+                    // the brightness follows the beats only, sustained
+                    // notes turn the hue instead
+                    if let Some(bass_intensity) =
+                        intensities.get(&IntensityID::get("bass_percussive"))
+                    {
                         let intensity: f64 = if self.is_silence {
                             1.0
                         } else {
@@ -110,12 +117,19 @@ impl Mode for AutoMode {
                         };
                         self.base_layer.mask.set_val(intensity);
                     }
+                    if let Some(harmonic) = intensities.get(&IntensityID::get("harmonic")) {
+                        if !self.is_silence {
+                            let drift = HUE_DRIFT * f64::from(*harmonic);
+                            self.base_layer.map.shift_hue(drift);
+                        }
+                    }
                 }
                 AudioEvent2::SilenceEnded => self.is_silence = false,
                 AudioEvent2::SilenceStarted => self.is_silence = true,
                 AudioEvent2::PhraseEnded => self.color_provider.set_random_color_set(),
             }
         }
+        events
     }
 
     fn periodic_update(&mut self) {}
@@ -0,0 +1,107 @@
+//! Test patterns for calibrating the lights on site.  Left and right
+//! on the controller go through the patterns, up and down change the
+//! brightness.  Holding L1 + R1 and pressing Select leaves the mode.
+use crate::light::color::{Color, ColorsExt};
+use crate::light::Coordinate;
+use crate::mode::Mode;
+use dualshock3::{Button, Controller};
+use log::info;
+use nightfire::audio::AudioEvent2;
+use pi_ir_remote::Signal;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Pattern {
+    Red,
+    Green,
+    Blue,
+    White,
+    /// Warm white at 2700 K and cold white at 6500 K, for lights with
+    /// white LEDs.
+    Warm,
+    Cold,
+    /// Grey from black on the left to the brightness on the right.
+    Ramp,
+    /// Like the ramp, in eight steps, to see where the low steps
+    /// become indistinguishable.
+    Steps,
+}
+
+const PATTERNS: [Pattern; 8] = [
+    Pattern::Red,
+    Pattern::Green,
+    Pattern::Blue,
+    Pattern::White,
+    Pattern::Warm,
+    Pattern::Cold,
+    Pattern::Ramp,
+    Pattern::Steps,
+];
+
+pub struct CalibrationMode {
+    pattern: usize,
+    brightness: f64,
+}
+
+impl CalibrationMode {
+    pub fn new() -> CalibrationMode {
+        CalibrationMode {
+            pattern: 0,
+            brightness: 1.,
+        }
+    }
+
+    fn pattern(&self) -> Pattern {
+        PATTERNS[self.pattern]
+    }
+
+    fn next_pattern(&mut self, step: isize) {
+        let n = PATTERNS.len() as isize;
+        self.pattern = (self.pattern as isize + step).rem_euclid(n) as usize;
+        info!("Calibration pattern: {:?}", self.pattern());
+    }
+
+    fn change_brightness(&mut self, step: f64) {
+        self.brightness = (self.brightness + step).clamp(0.1, 1.);
+        info!("Calibration brightness: {:.1}", self.brightness);
+    }
+}
+
+impl Mode for CalibrationMode {
+    fn get_color(&self, coordinate: &Coordinate) -> Color {
+        let ramp = ((coordinate.0 + 1.) / 2.).clamp(0., 1.);
+        let color = match self.pattern() {
+            Pattern::Red => Color::red(),
+            Pattern::Green => Color::green(),
+            Pattern::Blue => Color::blue(),
+            Pattern::White => Color::white(),
+            Pattern::Warm => Color::kelvin(2700.),
+            Pattern::Cold => Color::kelvin(6500.),
+            Pattern::Ramp => Color::white().mask(ramp),
+            Pattern::Steps => Color::white().mask(((ramp * 8.).floor() / 7.).min(1.)),
+        };
+        color.mask(self.brightness)
+    }
+
+    fn controller_update(&mut self, controller: &Controller) {
+        if controller.was_pressed(Button::Right) {
+            self.next_pattern(1);
+        }
+        if controller.was_pressed(Button::Left) {
+            self.next_pattern(-1);
+        }
+        if controller.was_pressed(Button::Up) {
+            self.change_brightness(0.1);
+        }
+        if controller.was_pressed(Button::Down) {
+            self.change_brightness(-0.1);
+        }
+    }
+
+    fn ir_remote_signal(&mut self, _signal: &Signal) {}
+
+    fn audio_update(&mut self, _frame: &[f32]) -> Vec<AudioEvent2> {
+        vec![]
+    }
+
+    fn periodic_update(&mut self) {}
+}
@@ -1,4 +1,4 @@
-use crate::light::cmap::StaticSolidMap;
+use crate::light::cmap::{Parameters, StaticSolidMap};
 use crate::light::color::{Color, ColorsExt};
 /// Idea: 2 blobs of color, one red one blue, one controlled with each stick.
 ///
@@ -8,6 +8,7 @@ use crate::light::Coordinate;
 use crate::mode::Mode;
 use crate::util::controller_coordinate_to_coordinate;
 use dualshock3::{Button, Controller};
+use nightfire::audio::AudioEvent2;
 use pi_ir_remote::Signal;
 use splines::{Interpolation, Key, Spline};
 
@@ -76,12 +77,14 @@ pub struct DoubleBlob {
     left_blob: SolidLayer<PosMask>,
     right_blob: SolidLayer<PosMask>,
     speed: Speed,
+    strobe: f64,
 }
 
 impl DoubleBlob {
     pub fn new() -> DoubleBlob {
         DoubleBlob {
             speed: Speed::new(0.5),
+            strobe: 0.,
             ltrigger_layer: SolidLayer::new(StaticSolidMap::new(Color::white()), SolidMask::new()),
             dpad_layer: SolidLayer::new(
                 StaticSolidMap::new(Color::green()),
@@ -129,8 +132,23 @@ impl Mode for DoubleBlob {
         c
     }
 
+    /// Moving heads follow the left blob, the right trigger strobes.
+    fn parameters(&self) -> Parameters {
+        let position = self.left_blob.mask.position;
+        Parameters {
+            // like the blob, the heads rest with the stick
+            position: if position.length() < 0.1 {
+                None
+            } else {
+                Some(position)
+            },
+            strobe: self.strobe,
+        }
+    }
+
     fn controller_update(&mut self, controller: &Controller) {
         self.ltrigger_layer.mask.set_val(controller.left_trigger());
+        self.strobe = controller.right_trigger();
         self.left_blob
             .mask
             .set_pos(controller_coordinate_to_coordinate(&controller.left_pos()));
@@ -166,6 +184,8 @@ impl Mode for DoubleBlob {
             _ => {}
         }
     }
-    fn audio_update(&mut self, _frame: &[f32]) {}
+    fn audio_update(&mut self, _frame: &[f32]) -> Vec<AudioEvent2> {
+        vec![]
+    }
     fn periodic_update(&mut self) {}
 }
@@ -81,9 +81,9 @@ impl Mode for HighLow {
         }
     }
 
-    fn audio_update(&mut self, frame: &[f32]) {
+    fn audio_update(&mut self, frame: &[f32]) -> Vec<AudioEvent2> {
         let events = self.signal_processor.add_audio_frame(frame);
-        for event in events {
+        for event in events.iter() {
             match event {
                 AudioEvent2::Intensities(intensities) => {
                     let mut highs_intensity = *intensities.get(&IntensityID::get("highs")).unwrap();
@@ -91,7 +91,9 @@ impl Mode for HighLow {
                         highs_intensity = 1.0;
                     }
                     self.color.mask.set_top(highs_intensity.into());
-                    let mut bass_intensity = *intensities.get(&IntensityID::get("bass")).unwrap();
+                    let mut bass_intensity = *intensities
+                        .get(&IntensityID::get("bass_percussive"))
+                        .unwrap();
                     if self.is_silence {
                         bass_intensity = 1.0;
                     }
@@ -102,6 +104,7 @@ impl Mode for HighLow {
                 _ => (),
             }
         }
+        events
     }
 
     fn periodic_update(&mut self) {}
@@ -152,12 +152,12 @@ impl Mode for DefaultMode {
         }
     }
 
-    fn audio_update(&mut self, frame: &[f32]) {
+    fn audio_update(&mut self, frame: &[f32]) -> Vec<AudioEvent2> {
         let events = self.signal_processor.add_audio_frame(frame);
-        for event in events {
+        for event in events.iter() {
             match event {
                 AudioEvent2::Onset(onset_id) => {
-                    if self.auto_rotate && onset_id == EdgeID::get("bass") {
+                    if self.auto_rotate && *onset_id == EdgeID::get("bass") {
                         self.state.manual_mode().rotate_cw();
                     }
                 }
@@ -195,6 +195,7 @@ impl Mode for DefaultMode {
                         state.manual_mode.set_bottom(c1);
                         state.manual_mode.set_top(c2);
                 */
+        events
     }
     fn periodic_update(&mut self) {
         self.state.periodic_update();
@@ -1,17 +1,25 @@
 pub mod auto;
+pub mod calibration;
 pub mod double_blob;
 pub mod high_low;
 pub mod manual;
 pub mod mode_switcher;
-use crate::light::cmap::ColorMap;
+pub mod spectrum;
+pub mod text;
+use crate::light::cmap::{ColorMap, Parameters};
 use crate::light::color::Color;
 use crate::light::coord::Coordinate;
+use crate::light::Image;
+use crate::osc::OscHandler;
+use crate::output::lut::LutSelection;
 use crate::periodic_updater::PeriodicUpdateHandler;
-use dualshock3::{Controller, ControllerHandler};
+use dualshock3::{Button, Controller, ControllerHandler};
 use mode_switcher::{ModeName, ModeSwitcher};
-use nf_audio::ValsHandler;
+use nf_audio::{Recorder, SourceStatus, ValsHandler};
+use nightfire::audio::{AudioEvent2, Preprocessor};
 use pi_ir_remote::Signal as IRSignal;
 use pi_ir_remote::SignalHandler as IRSignalHandler;
+use rosc::{OscMessage, OscType};
 use std::sync::{Arc, Mutex};
 
 /// A Mode is a struct that handles input such as controller input, IR remote input
@@ -19,65 +27,167 @@ use std::sync::{Arc, Mutex};
 /// or be only audio reactive or only controlled by the IR remote for example.
 pub trait Mode: Send + Sync {
     fn get_color(&self, coordinate: &Coordinate) -> Color;
+    /// Draws into the image of a matrix light, see `ColorMap`.  Most
+    /// modes only have get_color.
+    fn render_image(&self, _image: &mut Image) -> bool {
+        false
+    }
+    /// Pan, tilt and strobe for the fixtures that have them.
+    fn parameters(&self) -> Parameters {
+        Parameters::default()
+    }
     fn controller_update(&mut self, controller: &Controller);
     fn ir_remote_signal(&mut self, signal: &IRSignal);
-    fn audio_update(&mut self, frame: &[f32]);
+    /// Returns the audio events the mode reacted to, they go into the
+    /// log of the recorder.
+    fn audio_update(&mut self, frame: &[f32]) -> Vec<AudioEvent2>;
     fn periodic_update(&mut self);
 }
 
 pub struct Main {
     mode_switcher: Arc<Mutex<ModeSwitcher>>,
+    recorder: Option<Recorder>,
+    luts: Option<LutSelection>,
 }
 
 impl Main {
     pub fn new(sample_rate: f32) -> Main {
         Main {
             mode_switcher: Arc::new(Mutex::new(ModeSwitcher::new(ModeName::Auto1, sample_rate))),
+            recorder: None,
+            luts: None,
         }
     }
 
-    pub fn new_controller_handler(&mut self) -> Box<dyn ControllerHandler + Send + Sync> {
-        Box::new(Main {
+    /// With a recorder set, the detected audio events are logged to
+    /// it, and it can be dumped with the controller (L1 + R1 + Start),
+    /// the IR remote (Fade7) or OSC (`/nightfire/record/dump`).  Has to
+    /// be set before the handlers are created.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// The LUTs to switch between with the IR remote (White) or OSC
+    /// (`/nightfire/lut`).  Has to be set before the handlers are
+    /// created.
+    pub fn set_luts(&mut self, luts: LutSelection) {
+        self.luts = Some(luts);
+    }
+
+    /// The text shown by the text mode.
+    pub fn set_text(&mut self, text: &str) {
+        self.mode_switcher.lock().unwrap().set_text(text);
+    }
+
+    fn clone_handle(&self) -> Main {
+        Main {
             mode_switcher: Arc::clone(&self.mode_switcher),
-        })
+            recorder: self.recorder.clone(),
+            luts: self.luts.clone(),
+        }
     }
 
-    pub fn new_audio_handler(&mut self) -> Box<dyn ValsHandler + Send + Sync> {
-        Box::new(Main {
+    fn dump_recording(&self) {
+        match &self.recorder {
+            Some(recorder) => recorder.dump(),
+            None => log::warn!("No recorder configured, nothing to dump."),
+        }
+    }
+
+    pub fn new_controller_handler(&mut self) -> Box<dyn ControllerHandler + Send + Sync> {
+        Box::new(self.clone_handle())
+    }
+
+    /// The audio handler runs every frame through the given
+    /// preprocessor, before it is passed on to the current mode.
+    pub fn new_audio_handler(
+        &mut self,
+        preprocessor: Preprocessor,
+    ) -> Box<dyn ValsHandler + Send + Sync> {
+        Box::new(AudioHandler {
             mode_switcher: Arc::clone(&self.mode_switcher),
+            preprocessor,
+            recorder: self.recorder.clone(),
+            logged_mode: None,
         })
     }
 
     pub fn new_color_map(&mut self) -> Box<dyn ColorMap + Send + Sync> {
-        Box::new(Main {
-            mode_switcher: Arc::clone(&self.mode_switcher),
-        })
+        Box::new(self.clone_handle())
     }
 
     pub fn new_periodic_update_handler(&mut self) -> Box<dyn PeriodicUpdateHandler + Send + Sync> {
-        Box::new(Main {
-            mode_switcher: Arc::clone(&self.mode_switcher),
-        })
+        Box::new(self.clone_handle())
     }
 
     pub fn new_ir_remote_handler(&mut self) -> Box<dyn IRSignalHandler + Send + Sync> {
-        Box::new(Main {
-            mode_switcher: Arc::clone(&self.mode_switcher),
-        })
+        Box::new(self.clone_handle())
+    }
+
+    pub fn new_osc_handler(&mut self) -> Box<dyn OscHandler + Send + Sync> {
+        Box::new(self.clone_handle())
+    }
+
+    /// Dumps the recording, if there is a recorder.  For triggers from
+    /// outside, like a signal.
+    pub fn new_recording_dumper(&mut self) -> impl Fn() + Send {
+        let main = self.clone_handle();
+        move || main.dump_recording()
     }
 }
 
 impl ControllerHandler for Main {
     fn controller_update(&mut self, controller: &Controller) {
+        if controller.is_pressed(Button::L1)
+            && controller.is_pressed(Button::R1)
+            && controller.was_pressed(Button::Start)
+        {
+            self.dump_recording();
+            return;
+        }
         let mut ms = self.mode_switcher.lock().unwrap();
+        if controller.is_pressed(Button::L1)
+            && controller.is_pressed(Button::R1)
+            && controller.was_pressed(Button::Select)
+        {
+            ms.toggle_calibration();
+            return;
+        }
         ms.current_mode().controller_update(controller);
     }
 }
 
-impl ValsHandler for Main {
+struct AudioHandler {
+    mode_switcher: Arc<Mutex<ModeSwitcher>>,
+    preprocessor: Preprocessor,
+    /// Gets the events of the current mode.
+    recorder: Option<Recorder>,
+    /// The mode that the recorder last heard of.
+    logged_mode: Option<ModeName>,
+}
+
+impl ValsHandler for AudioHandler {
     fn take_frame(&mut self, frame: &[f32]) {
+        let frame = self.preprocessor.process_frame(frame);
+        let (mode, events) = {
+            let mut ms = self.mode_switcher.lock().unwrap();
+            let mode = ms.current_mode_name();
+            (mode, ms.current_mode().audio_update(&frame))
+        };
+        if let Some(recorder) = &self.recorder {
+            if self.logged_mode != Some(mode) {
+                recorder.log_mode(&mode.to_string());
+                self.logged_mode = Some(mode);
+            }
+            for label in events.iter().filter_map(|event| event.label()) {
+                recorder.log_event(&label);
+            }
+        }
+    }
+
+    fn status_changed(&mut self, status: &SourceStatus) {
         let mut ms = self.mode_switcher.lock().unwrap();
-        ms.current_mode().audio_update(&frame);
+        ms.set_audio_available(*status != SourceStatus::NoAudio);
     }
 }
 
@@ -86,6 +196,16 @@ impl ColorMap for Main {
         let ms = self.mode_switcher.lock().unwrap();
         ms.get_color(&coordinate)
     }
+
+    fn render_image(&self, image: &mut Image) -> bool {
+        let ms = self.mode_switcher.lock().unwrap();
+        ms.render_image(image)
+    }
+
+    fn parameters(&self) -> Parameters {
+        let ms = self.mode_switcher.lock().unwrap();
+        ms.parameters()
+    }
 }
 
 impl PeriodicUpdateHandler for Main {
@@ -122,6 +242,15 @@ impl IRSignalHandler for Main {
                 let mut ms = self.mode_switcher.lock().unwrap();
                 ms.activate_mode(ModeName::Auto2);
             }
+            IRSignal::Fade3 => {
+                let mut ms = self.mode_switcher.lock().unwrap();
+                ms.activate_next_matrix_mode();
+            }
+            IRSignal::Fade7 => self.dump_recording(),
+            IRSignal::White => match &self.luts {
+                Some(luts) => luts.next(),
+                None => log::warn!("No LUTs configured."),
+            },
             IRSignal::Power => {
                 println!("Power received");
                 let mut ms = self.mode_switcher.lock().unwrap();
@@ -134,3 +263,29 @@ impl IRSignalHandler for Main {
         }
     }
 }
+
+impl OscHandler for Main {
+    fn handle_message(&mut self, msg: &OscMessage) {
+        let arg = match msg.args.as_ref().and_then(|args| args.first()) {
+            Some(OscType::String(s)) => Some(s.as_str()),
+            _ => None,
+        };
+        match (msg.addr.as_str(), arg) {
+            ("/nightfire/record/dump", _) => self.dump_recording(),
+            ("/nightfire/text", Some(text)) => self.set_text(text),
+            ("/nightfire/mode", Some(name)) => match name.parse() {
+                Ok(mode) => self.mode_switcher.lock().unwrap().activate_mode(mode),
+                Err(e) => log::warn!("{}", e),
+            },
+            ("/nightfire/lut", Some(name)) => match &self.luts {
+                Some(luts) => {
+                    if let Err(e) = luts.select(name) {
+                        log::warn!("{}", e);
+                    }
+                }
+                None => log::warn!("No LUTs configured."),
+            },
+            _ => (),
+        }
+    }
+}
@@ -1,12 +1,20 @@
+use crate::light::cmap::Parameters;
 use crate::light::color::{Color, ColorsExt};
 use crate::light::coord::Coordinate;
+use crate::light::Image;
 use crate::mode::auto::AutoMode;
+use crate::mode::calibration::CalibrationMode;
 use crate::mode::double_blob::DoubleBlob;
 use crate::mode::high_low::HighLow;
 use crate::mode::manual::DefaultMode;
+use crate::mode::spectrum::Spectrum;
+use crate::mode::text::Text;
 use crate::mode::Mode;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ModeName {
     Auto1,
     Auto2,
@@ -14,6 +22,45 @@ pub enum ModeName {
     Manual2,
     DoubleBlob,
     HighLow,
+    Spectrum,
+    Text,
+    Calibration,
+}
+
+impl FromStr for ModeName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto1" => Ok(ModeName::Auto1),
+            "auto2" => Ok(ModeName::Auto2),
+            "manual1" => Ok(ModeName::Manual1),
+            "manual2" => Ok(ModeName::Manual2),
+            "double-blob" => Ok(ModeName::DoubleBlob),
+            "high-low" => Ok(ModeName::HighLow),
+            "spectrum" => Ok(ModeName::Spectrum),
+            "text" => Ok(ModeName::Text),
+            "calibration" => Ok(ModeName::Calibration),
+            other => Err(format!("Unknown mode: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for ModeName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ModeName::Auto1 => "auto1",
+            ModeName::Auto2 => "auto2",
+            ModeName::Manual1 => "manual1",
+            ModeName::Manual2 => "manual2",
+            ModeName::DoubleBlob => "double-blob",
+            ModeName::HighLow => "high-low",
+            ModeName::Spectrum => "spectrum",
+            ModeName::Text => "text",
+            ModeName::Calibration => "calibration",
+        };
+        f.write_str(name)
+    }
 }
 
 pub struct ModeSwitcher {
@@ -23,8 +70,15 @@ pub struct ModeSwitcher {
     manual2_mode: Box<dyn Mode>,
     double_blob: Box<dyn Mode>,
     high_low: Box<dyn Mode>,
+    spectrum: Box<dyn Mode>,
+    text: Text,
+    calibration: Box<dyn Mode>,
     c_mode: ModeName,
+    /// The mode to go back to when the calibration is done.
+    before_calibration: ModeName,
     off: bool,
+    /// Set while no audio source is available, see `set_audio_available`.
+    no_audio_since: Option<Instant>,
 }
 
 impl ModeSwitcher {
@@ -36,34 +90,77 @@ impl ModeSwitcher {
             manual2_mode: Box::new(DefaultMode::new(sample_rate)),
             double_blob: Box::new(DoubleBlob::new()),
             high_low: Box::new(HighLow::new(sample_rate)),
+            spectrum: Box::new(Spectrum::new(sample_rate)),
+            text: Text::new(),
+            calibration: Box::new(CalibrationMode::new()),
             c_mode: initial_mode,
+            before_calibration: initial_mode,
             off: false,
+            no_audio_since: None,
         }
     }
 
-    pub fn current_mode(&mut self) -> &mut Box<dyn Mode> {
+    pub fn current_mode(&mut self) -> &mut dyn Mode {
         match self.c_mode {
-            ModeName::Auto1 => &mut self.auto1_mode,
-            ModeName::Auto2 => &mut self.auto2_mode,
-            ModeName::Manual1 => &mut self.manual1_mode,
-            ModeName::Manual2 => &mut self.manual2_mode,
-            ModeName::DoubleBlob => &mut self.double_blob,
-            ModeName::HighLow => &mut self.high_low,
+            ModeName::Auto1 => self.auto1_mode.as_mut(),
+            ModeName::Auto2 => self.auto2_mode.as_mut(),
+            ModeName::Manual1 => self.manual1_mode.as_mut(),
+            ModeName::Manual2 => self.manual2_mode.as_mut(),
+            ModeName::DoubleBlob => self.double_blob.as_mut(),
+            ModeName::HighLow => self.high_low.as_mut(),
+            ModeName::Spectrum => self.spectrum.as_mut(),
+            ModeName::Text => &mut self.text,
+            ModeName::Calibration => self.calibration.as_mut(),
         }
     }
 
+    pub fn current_mode_name(&self) -> ModeName {
+        self.c_mode
+    }
+
+    fn mode(&self) -> &dyn Mode {
+        match self.c_mode {
+            ModeName::Auto1 => self.auto1_mode.as_ref(),
+            ModeName::Auto2 => self.auto2_mode.as_ref(),
+            ModeName::Manual1 => self.manual1_mode.as_ref(),
+            ModeName::Manual2 => self.manual2_mode.as_ref(),
+            ModeName::DoubleBlob => self.double_blob.as_ref(),
+            ModeName::HighLow => self.high_low.as_ref(),
+            ModeName::Spectrum => self.spectrum.as_ref(),
+            ModeName::Text => &self.text,
+            ModeName::Calibration => self.calibration.as_ref(),
+        }
+    }
+
+    /// Whether the "off" or "no audio" colors are shown instead of the
+    /// current mode.
+    fn is_overridden(&self) -> bool {
+        self.off || (self.no_audio_since.is_some() && self.is_audio_mode())
+    }
+
     pub fn get_color(&self, coordinate: &Coordinate) -> Color {
         if self.off {
             Color::black()
+        } else if let (Some(t), true) = (self.no_audio_since, self.is_audio_mode()) {
+            // slow dim red pulse, so it's obvious the audio is missing
+            let phase = (t.elapsed().as_secs_f64() * std::f64::consts::PI / 2.).sin();
+            Color::red().mask(0.05 + 0.1 * phase * phase)
         } else {
-            match self.c_mode {
-                ModeName::Auto1 => self.auto1_mode.get_color(coordinate),
-                ModeName::Auto2 => self.auto2_mode.get_color(coordinate),
-                ModeName::Manual1 => self.manual1_mode.get_color(coordinate),
-                ModeName::Manual2 => self.manual2_mode.get_color(coordinate),
-                ModeName::DoubleBlob => self.double_blob.get_color(coordinate),
-                ModeName::HighLow => self.high_low.get_color(coordinate),
-            }
+            self.mode().get_color(coordinate)
+        }
+    }
+
+    /// Lets the current mode draw into a matrix image, false if it
+    /// can't or the mode is overridden.
+    pub fn render_image(&self, image: &mut Image) -> bool {
+        !self.is_overridden() && self.mode().render_image(image)
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        if self.is_overridden() {
+            Parameters::default()
+        } else {
+            self.mode().parameters()
         }
     }
 
@@ -71,7 +168,51 @@ impl ModeSwitcher {
         self.c_mode = mode;
     }
 
+    /// Switches to the test patterns, or back to the mode before.
+    pub fn toggle_calibration(&mut self) {
+        self.c_mode = match self.c_mode {
+            ModeName::Calibration => self.before_calibration,
+            other => {
+                self.before_calibration = other;
+                ModeName::Calibration
+            }
+        };
+    }
+
+    /// Goes through the modes made for matrix lights.
+    pub fn activate_next_matrix_mode(&mut self) {
+        self.c_mode = match self.c_mode {
+            ModeName::Spectrum => ModeName::Text,
+            _ => ModeName::Spectrum,
+        };
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text.set_text(text);
+    }
+
     pub fn switch_on_off(&mut self) {
         self.off = !self.off;
     }
+
+    /// When the audio input is lost, the modes that only react to
+    /// audio show a "no audio" pulse instead.
+    pub fn set_audio_available(&mut self, available: bool) {
+        if available {
+            self.no_audio_since = None;
+        } else if self.no_audio_since.is_none() {
+            self.no_audio_since = Some(Instant::now());
+        }
+    }
+
+    fn is_audio_mode(&self) -> bool {
+        match self.c_mode {
+            ModeName::Auto1 | ModeName::Auto2 | ModeName::HighLow | ModeName::Spectrum => true,
+            ModeName::Manual1
+            | ModeName::Manual2
+            | ModeName::DoubleBlob
+            | ModeName::Text
+            | ModeName::Calibration => false,
+        }
+    }
 }
@@ -0,0 +1,98 @@
+//! Spectrum bars, made for matrix lights.  Every column shows a
+//! frequency band, low on the left, and the bars grow from the bottom.
+use crate::light::color::{Color, ColorsExt};
+use crate::light::{Coordinate, Image};
+use crate::mode::Mode;
+use dualshock3::Controller;
+use nightfire::audio::{AudioEvent2, SignalFilter};
+use pi_ir_remote::Signal;
+
+const N_BANDS: usize = 16;
+/// How much of the full height a bar falls per second.
+const FALL_PER_SECOND: f32 = 1.5;
+/// The bars are relative to the loudest band lately, which halves
+/// in this many seconds if it isn't reached again.
+const PEAK_HALF_LIFE: f32 = 5.;
+/// The image that lights without a matrix sample from.
+const PREVIEW_WIDTH: usize = N_BANDS;
+const PREVIEW_HEIGHT: usize = 8;
+
+pub struct Spectrum {
+    filter: SignalFilter,
+    sample_rate: f32,
+    peak: f32,
+    /// The height of every bar, from 0 to 1.
+    levels: Vec<f32>,
+    preview: Image,
+}
+
+impl Spectrum {
+    pub fn new(sample_rate: f32) -> Spectrum {
+        Spectrum {
+            filter: SignalFilter::new(40., 12_000., sample_rate, 2., N_BANDS),
+            sample_rate,
+            peak: 0.,
+            levels: vec![0.; N_BANDS],
+            preview: Image::new(PREVIEW_WIDTH, PREVIEW_HEIGHT),
+        }
+    }
+
+    /// Green at the bottom, over yellow to red at the top.
+    fn bar_color(height: f64) -> Color {
+        Color::new((height * 2.).min(1.), ((1. - height) * 2.).min(1.), 0.)
+    }
+
+    fn draw(&self, image: &mut Image) {
+        image.fill(Color::black());
+        let (width, height) = (image.width(), image.height());
+        for x in 0..width {
+            let level = self.levels[x * N_BANDS / width];
+            let bar = (level * height as f32).round() as usize;
+            for i in 0..bar.min(height) {
+                let color = Spectrum::bar_color(i as f64 / height as f64);
+                image.set(x as i64, (height - 1 - i) as i64, color);
+            }
+        }
+    }
+}
+
+impl Mode for Spectrum {
+    fn get_color(&self, coordinate: &Coordinate) -> Color {
+        self.preview.sample(coordinate)
+    }
+
+    fn render_image(&self, image: &mut Image) -> bool {
+        self.draw(image);
+        true
+    }
+
+    fn controller_update(&mut self, _controller: &Controller) {}
+
+    fn ir_remote_signal(&mut self, _signal: &Signal) {}
+
+    fn audio_update(&mut self, frame: &[f32]) -> Vec<AudioEvent2> {
+        let mut bands = vec![0f32; N_BANDS];
+        for x in frame {
+            for (band, val) in bands.iter_mut().zip(self.filter.get_filter_vals(x)) {
+                *band = band.max(val.abs());
+            }
+        }
+        let dt = frame.len() as f32 / self.sample_rate;
+        let loudest = bands.iter().cloned().fold(0., f32::max);
+        self.peak = (self.peak * 0.5f32.powf(dt / PEAK_HALF_LIFE))
+            .max(loudest)
+            .max(1e-4);
+        for (level, band) in self.levels.iter_mut().zip(bands) {
+            // jump up, fall slowly
+            let target = band / self.peak;
+            *level = target.max(*level - FALL_PER_SECOND * dt);
+        }
+        vec![]
+    }
+
+    fn periodic_update(&mut self) {
+        let mut preview = Image::new(PREVIEW_WIDTH, PREVIEW_HEIGHT);
+        self.draw(&mut preview);
+        self.preview = preview;
+    }
+}
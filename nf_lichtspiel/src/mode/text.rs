@@ -0,0 +1,97 @@
+//! Scrolling text, made for matrix lights.  The text is set over OSC
+//! or in the config, the color slowly cycles through the rainbow.
+use crate::light::color::{Color, ColorsExt, Rainbow};
+use crate::light::font::GLYPH_HEIGHT;
+use crate::light::image::{text_advance, text_scale};
+use crate::light::{Coordinate, Image};
+use crate::mode::Mode;
+use dualshock3::Controller;
+use nightfire::audio::AudioEvent2;
+use pi_ir_remote::Signal;
+use std::time::Instant;
+
+/// The image that lights without a matrix sample from.
+const PREVIEW_WIDTH: usize = 32;
+const PREVIEW_HEIGHT: usize = 7;
+
+pub struct Text {
+    text: String,
+    /// In font pixels per second.
+    speed: f64,
+    /// How far the text has moved, in font pixels, at t_scrolled.
+    scrolled: f64,
+    t_scrolled: Instant,
+    rainbow: Rainbow,
+    preview: Image,
+}
+
+impl Text {
+    pub fn new() -> Text {
+        Text {
+            text: "nightfire".to_string(),
+            speed: 8.,
+            scrolled: 0.,
+            t_scrolled: Instant::now(),
+            rainbow: Rainbow::new(),
+            preview: Image::new(PREVIEW_WIDTH, PREVIEW_HEIGHT),
+        }
+    }
+
+    /// Sets the text and starts scrolling it in from the right.
+    pub fn set_text(&mut self, text: &str) {
+        self.text = text.to_string();
+        self.scrolled = 0.;
+        self.t_scrolled = Instant::now();
+    }
+
+    fn scrolled_now(&self) -> f64 {
+        self.scrolled + self.t_scrolled.elapsed().as_secs_f64() * self.speed
+    }
+
+    fn draw(&self, image: &mut Image) {
+        image.fill(Color::black());
+        let (width, height) = (image.width(), image.height());
+        let scale = text_scale(height);
+        let text_width = self.text.chars().count() * text_advance(scale);
+        // the text runs out on the left before it comes in again
+        let period = text_width + width;
+        let offset = (self.scrolled_now() * scale as f64) as usize;
+        let x = width as i64 - (offset % period) as i64;
+        let y = height.saturating_sub(GLYPH_HEIGHT * scale) as i64 / 2;
+        image.draw_text(&self.text, x, y, scale, self.rainbow.get_color());
+    }
+}
+
+impl Mode for Text {
+    fn get_color(&self, coordinate: &Coordinate) -> Color {
+        self.preview.sample(coordinate)
+    }
+
+    fn render_image(&self, image: &mut Image) -> bool {
+        self.draw(image);
+        true
+    }
+
+    fn controller_update(&mut self, _controller: &Controller) {}
+
+    fn ir_remote_signal(&mut self, signal: &Signal) {
+        // keep the position when the speed changes
+        self.scrolled = self.scrolled_now();
+        self.t_scrolled = Instant::now();
+        match signal {
+            Signal::Quick => self.speed = (self.speed * 1.25).min(64.),
+            Signal::Slow => self.speed = (self.speed / 1.25).max(1.),
+            _ => (),
+        }
+    }
+
+    fn audio_update(&mut self, _frame: &[f32]) -> Vec<AudioEvent2> {
+        vec![]
+    }
+
+    fn periodic_update(&mut self) {
+        let mut preview = Image::new(PREVIEW_WIDTH, PREVIEW_HEIGHT);
+        self.draw(&mut preview);
+        self.preview = preview;
+    }
+}
@@ -0,0 +1,50 @@
+use log::{debug, info, warn};
+use rosc::{OscMessage, OscPacket};
+use std::net::UdpSocket;
+use std::time::Duration;
+use stoppable_thread::{spawn, StoppableHandle};
+
+/// Listens for OSC messages on the given UDP port and passes them to
+/// the handler.  Bundles are unpacked.
+pub fn start_osc_thread(
+    mut handler: Box<dyn OscHandler + Send + Sync>,
+    port: u16,
+) -> StoppableHandle<()> {
+    let socket = UdpSocket::bind(("0.0.0.0", port)).expect("Failed to bind OSC port");
+    // time out regularly, so the thread can be stopped
+    socket
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    info!("Listening for OSC messages on port {}.", port);
+    spawn(move |stopped| {
+        let mut buf = [0u8; rosc::decoder::MTU];
+        while !stopped.get() {
+            let size = match socket.recv_from(&mut buf) {
+                Ok((size, _)) => size,
+                Err(_) => continue,
+            };
+            match rosc::decoder::decode(&buf[..size]) {
+                Ok(packet) => handle_packet(&mut handler, packet),
+                Err(e) => warn!("Received invalid OSC packet: {:?}", e),
+            }
+        }
+    })
+}
+
+fn handle_packet(handler: &mut Box<dyn OscHandler + Send + Sync>, packet: OscPacket) {
+    match packet {
+        OscPacket::Message(msg) => {
+            debug!("OSC message: {:?}", msg);
+            handler.handle_message(&msg);
+        }
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                handle_packet(handler, packet);
+            }
+        }
+    }
+}
+
+pub trait OscHandler {
+    fn handle_message(&mut self, msg: &OscMessage);
+}
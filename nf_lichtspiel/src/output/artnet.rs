@@ -0,0 +1,234 @@
+//! Art-Net output: DMX over UDP, sent as ArtDmx packets, one per
+//! universe and frame.
+use super::dmx::{DmxMapping, Universe};
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::cmap::Parameters;
+use crate::light::Color;
+use log::{info, warn};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const ARTNET_PORT: u16 = 6454;
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+
+fn header(op_code: u16) -> Vec<u8> {
+    let mut buf = ID.to_vec();
+    buf.extend_from_slice(&op_code.to_le_bytes());
+    buf.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    buf
+}
+
+/// An ArtDmx packet.  The universe is the 15 bit port address,
+/// `net << 8 | sub_net << 4 | universe`.
+pub fn art_dmx_packet(sequence: u8, universe: u16, data: &Universe) -> Vec<u8> {
+    let mut buf = header(OP_DMX);
+    buf.push(sequence);
+    buf.push(0); // physical port
+    buf.extend_from_slice(&(universe & 0x7fff).to_le_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub struct ArtNet {
+    socket: UdpSocket,
+    target: SocketAddr,
+    mapping: DmxMapping,
+    /// Counts from 1 to 255, 0 would turn off reordering in the nodes.
+    sequence: u8,
+}
+
+impl ArtNet {
+    /// Sends to a single node, or to all nodes in the network if the
+    /// address is a broadcast address like `2.255.255.255`.
+    pub fn new(address: &str, mapping: DmxMapping) -> Result<ArtNet, String> {
+        let address: Ipv4Addr = address
+            .parse()
+            .map_err(|_| format!("Invalid Art-Net address: {}", address))?;
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|socket| socket.set_broadcast(true).map(|()| socket))
+            .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+        info!("Sending Art-Net to {}.", address);
+        Ok(ArtNet {
+            socket,
+            target: SocketAddr::from((address, ARTNET_PORT)),
+            mapping,
+            sequence: 0,
+        })
+    }
+
+    /// Looks for nodes in the background, see `discover`.
+    pub fn discover(&self) {
+        let target = self.target;
+        thread::spawn(move || discover(target));
+    }
+
+    fn send(&mut self, universes: impl IntoIterator<Item = (u16, Universe)>) {
+        self.sequence = self.sequence % 255 + 1;
+        for (universe, data) in universes {
+            let packet = art_dmx_packet(self.sequence, universe, &data);
+            if let Err(e) = self.socket.send_to(&packet, self.target) {
+                warn!("Failed to send Art-Net packet: {}", e);
+            }
+        }
+    }
+}
+
+/// Sends an ArtPoll and logs the nodes that reply within a second.
+/// The replies go to the Art-Net port, so this only works if no other
+/// Art-Net software on this machine has it open.
+fn discover(target: SocketAddr) {
+    let socket = match UdpSocket::bind(("0.0.0.0", ARTNET_PORT)).and_then(|socket| {
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(socket)
+    }) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Art-Net discovery not possible: {}", e);
+            return;
+        }
+    };
+    let mut poll = header(OP_POLL);
+    poll.extend_from_slice(&[0, 0]); // flags, diagnostics priority
+    if let Err(e) = socket.send_to(&poll, target) {
+        warn!("Failed to send ArtPoll: {}", e);
+        return;
+    }
+    let t_start = Instant::now();
+    let mut buf = [0u8; 1024];
+    while t_start.elapsed() < Duration::from_secs(1) {
+        let size = match socket.recv(&mut buf) {
+            Ok(size) => size,
+            Err(_) => continue,
+        };
+        let reply = &buf[..size];
+        if size < 44 || &reply[..8] != ID || reply[8..10] != OP_POLL_REPLY.to_le_bytes() {
+            continue;
+        }
+        let ip = Ipv4Addr::new(reply[10], reply[11], reply[12], reply[13]);
+        let name = String::from_utf8_lossy(&reply[26..44]);
+        info!(
+            "Found Art-Net node {} at {}.",
+            name.trim_end_matches('\0'),
+            ip
+        );
+    }
+}
+
+impl OutputBackend for ArtNet {
+    fn write_frame(&mut self, frame: &Frame) {
+        let universes = self.mapping.render(frame);
+        self.send(universes);
+    }
+
+    fn set_parameters(&mut self, parameters: &Parameters) {
+        self.mapping.set_parameters(parameters);
+    }
+
+    fn shutdown(&mut self) {
+        let blackout: Vec<(u16, Universe)> = self
+            .mapping
+            .universes()
+            .into_iter()
+            .map(|u| (u, [0; 512]))
+            .collect();
+        self.send(blackout);
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.mapping.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::dmx::DmxLight;
+    use crate::output::fixture::{Aim, Profile};
+    use crate::output::white::WhiteLeds;
+
+    fn rgb_light(name: &str, universe: u16, channel: usize) -> (LightId, DmxLight) {
+        let light = DmxLight {
+            universe,
+            channel,
+            profile: Profile::builtin("rgb").unwrap(),
+            pixels: 1,
+            whites: WhiteLeds::default(),
+            aim: Aim::default(),
+        };
+        (LightId::get(name), light)
+    }
+
+    #[test]
+    fn art_dmx_header() {
+        let mut data = [0; 512];
+        data[0] = 255;
+        data[511] = 7;
+        let packet = art_dmx_packet(42, 0x0123, &data);
+        assert_eq!(packet.len(), 18 + 512);
+        assert_eq!(&packet[..8], b"Art-Net\0");
+        assert_eq!(packet[8..10], [0x00, 0x50]); // OpDmx, little endian
+        assert_eq!(packet[10..12], [0, 14]); // protocol version, big endian
+        assert_eq!(packet[12], 42); // sequence
+        assert_eq!(packet[13], 0); // physical port
+        assert_eq!(packet[14..16], [0x23, 0x01]); // sub-net and universe, net
+        assert_eq!(packet[16..18], [0x02, 0x00]); // length, big endian
+        assert_eq!(packet[18], 255);
+        assert_eq!(packet[18 + 511], 7);
+    }
+
+    #[test]
+    fn port_address_has_15_bits() {
+        let packet = art_dmx_packet(1, 0xffff, &[0; 512]);
+        assert_eq!(packet[14..16], [0xff, 0x7f]);
+    }
+
+    #[test]
+    fn invalid_address_is_an_error() {
+        assert!(ArtNet::new("2.255.255.256", DmxMapping::new(vec![])).is_err());
+        assert!(ArtNet::new("node.local", DmxMapping::new(vec![])).is_err());
+        assert!(ArtNet::new("2.255.255.255", DmxMapping::new(vec![])).is_ok());
+    }
+
+    #[test]
+    fn sends_each_universe_over_loopback() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut artnet = ArtNet {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            target: receiver.local_addr().unwrap(),
+            mapping: DmxMapping::new(vec![rgb_light("Top", 0, 1), rgb_light("Bottom", 1, 510)]),
+            sequence: 254,
+        };
+        let frame = vec![
+            (LightId::get("Top"), vec![Color::new(1., 0., 0.)]),
+            (LightId::get("Bottom"), vec![Color::new(0., 0., 1.)]),
+        ];
+        let mut buf = [0u8; 1024];
+        // the sequence skips 0, which would turn off reordering
+        for sequence in [255, 1] {
+            artnet.write_frame(&frame);
+            for universe in 0..2u16 {
+                let size = receiver.recv(&mut buf).unwrap();
+                let packet = &buf[..size];
+                assert_eq!(size, 18 + 512);
+                assert_eq!(packet[12], sequence);
+                assert_eq!(packet[14..16], universe.to_le_bytes());
+                let data = &packet[18..];
+                match universe {
+                    0 => assert_eq!(data[..3], [255, 0, 0]),
+                    _ => assert_eq!(data[509..], [0, 0, 255]),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Per light color correction, for LEDs that don't agree on what
+//! white is.  It works on the brightness that is put out, after the
+//! curve of the output.
+use crate::light::{Color, PinValue};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Calibration {
+    /// Mixes the channels, every row makes one output channel from r,
+    /// g and b.
+    pub matrix: [[PinValue; 3]; 3],
+    /// Scales r, g and b after the matrix, for the white balance.
+    pub gains: [PinValue; 3],
+    /// No channel goes above this, for lights that are too bright or
+    /// get too hot at full power.
+    pub max: PinValue,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            matrix: [[1., 0., 0.], [0., 1., 0.], [0., 0., 1.]],
+            gains: [1., 1., 1.],
+            max: 1.,
+        }
+    }
+}
+
+impl Calibration {
+    pub fn is_identity(&self) -> bool {
+        *self == Calibration::default()
+    }
+
+    pub fn apply(&self, color: &Color) -> Color {
+        let rgb = [color.red, color.green, color.blue];
+        let channel = |i: usize| {
+            let mixed: PinValue = self.matrix[i].iter().zip(&rgb).map(|(m, v)| m * v).sum();
+            (mixed * self.gains[i]).clamp(0., self.max)
+        };
+        Color::new(channel(0), channel(1), channel(2))
+    }
+}
@@ -0,0 +1,153 @@
+//! DDP output, the realtime UDP protocol of WLED and other pixel
+//! controllers.  A frame is split into packets of up to 480 pixels,
+//! the last one tells the controller to show the frame.
+use super::pixels::PixelMapping;
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::Color;
+use log::{info, warn};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+pub const DDP_PORT: u16 = 4048;
+const MAX_DATA_LEN: usize = 1440;
+const FLAG_VERSION_1: u8 = 0x40;
+const FLAG_PUSH: u8 = 0x01;
+/// RGB (type 001) with 8 bits per channel (size 011).
+const DATA_TYPE_RGB: u8 = 0x0b;
+const ID_DISPLAY: u8 = 1;
+
+/// A DDP packet with the data for the pixels starting at the byte
+/// `offset`.
+pub fn ddp_packet(sequence: u8, push: bool, offset: usize, data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![
+        FLAG_VERSION_1 | if push { FLAG_PUSH } else { 0 },
+        sequence & 0x0f,
+        DATA_TYPE_RGB,
+        ID_DISPLAY,
+    ];
+    buf.extend_from_slice(&(offset as u32).to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub struct Ddp {
+    socket: UdpSocket,
+    target: SocketAddr,
+    mapping: PixelMapping,
+    /// Counts from 1 to 15, 0 means not used.
+    sequence: u8,
+}
+
+impl Ddp {
+    /// The port can be left out of the address.
+    pub fn new(address: &str, mapping: PixelMapping) -> Result<Ddp, String> {
+        let target = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DDP_PORT)
+        };
+        let target = target
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid DDP address {}: {}", target, e))?
+            .next()
+            .ok_or_else(|| format!("DDP address {} not found", target))?;
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+        info!("Sending DDP to {}.", target);
+        Ok(Ddp {
+            socket,
+            target,
+            mapping,
+            sequence: 0,
+        })
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        self.sequence = self.sequence % 15 + 1;
+        let n_packets = data.len().div_ceil(MAX_DATA_LEN);
+        for (i, chunk) in data.chunks(MAX_DATA_LEN).enumerate() {
+            let packet = ddp_packet(self.sequence, i + 1 == n_packets, i * MAX_DATA_LEN, chunk);
+            if let Err(e) = self.socket.send_to(&packet, self.target) {
+                warn!("Failed to send DDP packet: {}", e);
+            }
+        }
+    }
+}
+
+impl OutputBackend for Ddp {
+    fn write_frame(&mut self, frame: &Frame) {
+        let data = self.mapping.render(frame);
+        self.send(&data);
+    }
+
+    fn shutdown(&mut self) {
+        self.send(&vec![0; self.mapping.n_pixels() * 3]);
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.mapping.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::pixels::PixelLight;
+    use std::time::Duration;
+
+    #[test]
+    fn ddp_header() {
+        let packet = ddp_packet(3, true, 0x010203, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(packet.len(), 10 + 6);
+        assert_eq!(packet[0], 0x41); // version 1, push
+        assert_eq!(packet[1], 3);
+        assert_eq!(packet[2], 0x0b);
+        assert_eq!(packet[3], 1);
+        assert_eq!(packet[4..8], [0x00, 0x01, 0x02, 0x03]);
+        assert_eq!(packet[8..10], [0, 6]);
+        assert_eq!(packet[10..], [1, 2, 3, 4, 5, 6]);
+        let packet = ddp_packet(0x13, false, 0, &[]);
+        assert_eq!(packet[0], 0x40);
+        assert_eq!(packet[1], 3);
+    }
+
+    #[test]
+    fn invalid_address_is_an_error() {
+        assert!(Ddp::new("127.0.0.1:port", PixelMapping::new(vec![])).is_err());
+        assert!(Ddp::new("127.0.0.1", PixelMapping::new(vec![])).is_ok());
+    }
+
+    #[test]
+    fn splits_frames_and_wraps_the_sequence() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let light = PixelLight {
+            start: 0,
+            count: 500,
+        };
+        let mut ddp = Ddp::new(
+            &receiver.local_addr().unwrap().to_string(),
+            PixelMapping::new(vec![(LightId::get("Strip"), light)]),
+        )
+        .unwrap();
+        ddp.sequence = 14;
+        let frame = vec![(LightId::get("Strip"), vec![Color::new(1., 0., 0.); 500])];
+        let mut buf = [0u8; 2048];
+        for sequence in [15, 1] {
+            ddp.write_frame(&frame);
+            // 1500 bytes, the last packet shows the frame
+            for (offset, len, push) in [(0, MAX_DATA_LEN, false), (MAX_DATA_LEN, 60, true)] {
+                let size = receiver.recv(&mut buf).unwrap();
+                assert_eq!(size, 10 + len);
+                assert_eq!(buf[0] & FLAG_PUSH == FLAG_PUSH, push);
+                assert_eq!(buf[1], sequence);
+                assert_eq!(buf[4..8], (offset as u32).to_be_bytes());
+                assert_eq!(buf[8..10], (len as u16).to_be_bytes());
+                assert_eq!(buf[10..13], [255, 0, 0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Maps lights to DMX channels, shared by the DMX based outputs.
+use super::fixture::{Aim, Profile};
+use super::white::WhiteLeds;
+use super::{Duty, Frame};
+use crate::layout::LightId;
+use crate::light::cmap::Parameters;
+use crate::light::Color;
+use std::collections::{BTreeMap, HashMap};
+
+pub type Universe = [u8; 512];
+
+/// Where a light is, `channel` counts from 1 like on the fixtures.
+/// The pixels of a light with several pixels, like a pixel bar, follow
+/// each other.
+#[derive(Debug, Clone)]
+pub struct DmxLight {
+    pub universe: u16,
+    pub channel: usize,
+    pub profile: Profile,
+    pub pixels: usize,
+    /// For the profiles with white channels.
+    pub whites: WhiteLeds,
+    /// For moving heads.
+    pub aim: Aim,
+}
+
+pub struct DmxMapping {
+    lights: HashMap<LightId, DmxLight>,
+    parameters: Parameters,
+}
+
+impl DmxMapping {
+    pub fn new(lights: Vec<(LightId, DmxLight)>) -> DmxMapping {
+        for (id, light) in &lights {
+            let n = light.profile.n_channels() * light.pixels;
+            if light.channel == 0 || light.channel + n - 1 > 512 {
+                panic!("DMX channels of {:?} are out of range", id);
+            }
+        }
+        DmxMapping {
+            lights: lights.into_iter().collect(),
+            parameters: Parameters::default(),
+        }
+    }
+
+    /// The parameters for the next frames.
+    pub fn set_parameters(&mut self, parameters: &Parameters) {
+        self.parameters = *parameters;
+    }
+
+    /// The universes that are used, so they can be sent even if no
+    /// light in them is in the frame.
+    pub fn universes(&self) -> Vec<u16> {
+        let mut universes: Vec<u16> = self.lights.values().map(|l| l.universe).collect();
+        universes.sort_unstable();
+        universes.dedup();
+        universes
+    }
+
+    /// The brightness of the LEDs of a pixel of the light, None if the
+    /// light isn't mapped.
+    pub fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.lights
+            .get(id)
+            .map(|light| light.profile.duty(color, &light.whites))
+    }
+
+    /// The channel data of all used universes.
+    pub fn render(&self, frame: &Frame) -> BTreeMap<u16, Universe> {
+        let mut universes: BTreeMap<u16, Universe> =
+            self.universes().into_iter().map(|u| (u, [0; 512])).collect();
+        for (id, colors) in frame {
+            if let Some(light) = self.lights.get(id) {
+                let data = universes.get_mut(&light.universe).unwrap();
+                let values = colors.iter().take(light.pixels).flat_map(|c| {
+                    light
+                        .profile
+                        .render(c, &light.whites, &light.aim, &self.parameters)
+                });
+                for (i, value) in values.enumerate() {
+                    data[light.channel - 1 + i] = value;
+                }
+            }
+        }
+        universes
+    }
+}
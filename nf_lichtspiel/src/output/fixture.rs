@@ -0,0 +1,241 @@
+//! Fixture profiles, what each DMX channel of a fixture does.  The
+//! simple layouts like `rgb` are built in, others are read from a
+//! directory of YAML files, see `fixtures/` for examples.
+use super::white::WhiteLeds;
+use super::Duty;
+use crate::light::cmap::Parameters;
+use crate::light::{Color, PinValue};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    /// A single white.
+    White,
+    Warm,
+    Cold,
+    /// The color temperature from warm at 0 to cold at full, for
+    /// fixtures with a dimmer.
+    Temperature,
+    /// The master dimmer, the colors are at full scale behind it.
+    Dimmer,
+    Strobe,
+    Pan,
+    PanFine,
+    Tilt,
+    TiltFine,
+    /// Always the same value, for channels like programs or speed.
+    Fixed(u8),
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "red" => Ok(Channel::Red),
+            "green" => Ok(Channel::Green),
+            "blue" => Ok(Channel::Blue),
+            "white" => Ok(Channel::White),
+            "warm" => Ok(Channel::Warm),
+            "cold" => Ok(Channel::Cold),
+            "temperature" => Ok(Channel::Temperature),
+            "dimmer" => Ok(Channel::Dimmer),
+            "strobe" => Ok(Channel::Strobe),
+            "pan" => Ok(Channel::Pan),
+            "pan-fine" => Ok(Channel::PanFine),
+            "tilt" => Ok(Channel::Tilt),
+            "tilt-fine" => Ok(Channel::TiltFine),
+            other => match other.parse() {
+                Ok(value) => Ok(Channel::Fixed(value)),
+                Err(_) => Err(format!("Unknown channel: {}", other)),
+            },
+        }
+    }
+}
+
+/// The part of the pan and tilt range a moving head uses, as a
+/// fraction of the full range.  The left and bottom of the layout go
+/// to the first value, the right and top to the second.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Aim {
+    pub pan: (PinValue, PinValue),
+    pub tilt: (PinValue, PinValue),
+}
+
+impl Default for Aim {
+    fn default() -> Self {
+        Aim {
+            pan: (0., 1.),
+            tilt: (0., 1.),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub channels: Vec<Channel>,
+    /// The strobe values for no strobe, the slowest and the fastest.
+    pub strobe: [u8; 3],
+    /// The white LEDs, unless a light says otherwise.
+    pub whites: WhiteLeds,
+}
+
+fn to_dmx(value: PinValue) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// A value in [0, 1] as coarse and fine 16 bit channels.
+fn to_dmx16(value: PinValue) -> (u8, u8) {
+    let value = (value.clamp(0., 1.) * 65535.).round() as u16;
+    ((value >> 8) as u8, value as u8)
+}
+
+impl Profile {
+    pub fn new(channels: Vec<Channel>) -> Profile {
+        let tunable = channels
+            .iter()
+            .any(|c| matches!(c, Channel::Warm | Channel::Cold | Channel::Temperature));
+        Profile {
+            channels,
+            strobe: [0, 10, 255],
+            whites: if tunable {
+                WhiteLeds::tunable(2700., 6500.)
+            } else {
+                WhiteLeds::default()
+            },
+        }
+    }
+
+    /// The layouts that need no profile file.
+    pub fn builtin(name: &str) -> Option<Profile> {
+        use Channel::*;
+        let channels = match name {
+            "rgb" => vec![Red, Green, Blue],
+            "rgbw" => vec![Red, Green, Blue, White],
+            "rgbww" => vec![Red, Green, Blue, Warm, Cold],
+            "cct" => vec![Warm, Cold],
+            "dimmer-cct" => vec![Dimmer, Temperature],
+            "dimmer-rgb" => vec![Dimmer, Red, Green, Blue],
+            _ => return None,
+        };
+        Some(Profile::new(channels))
+    }
+
+    /// The number of channels per pixel.
+    pub fn n_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    fn has(&self, channel: Channel) -> bool {
+        self.channels.contains(&channel)
+    }
+
+    pub fn is_tunable(&self) -> bool {
+        self.has(Channel::Warm) || self.has(Channel::Cold) || self.has(Channel::Temperature)
+    }
+
+    fn has_rgb(&self) -> bool {
+        self.has(Channel::Red) || self.has(Channel::Green) || self.has(Channel::Blue)
+    }
+
+    fn has_white(&self) -> bool {
+        self.is_tunable() || self.has(Channel::White)
+    }
+
+    /// What of the color goes to r, g and b, and what to the whites.
+    fn split(&self, color: &Color, whites: &WhiteLeds) -> (Color, [PinValue; 2]) {
+        if self.has_white() {
+            whites.split(color, self.has_rgb())
+        } else {
+            (*color, [0.; 2])
+        }
+    }
+
+    /// The brightness of the LEDs of one pixel.  Fixtures with only a
+    /// dimmer count as white.
+    pub fn duty(&self, color: &Color, whites: &WhiteLeds) -> Duty {
+        if !self.has_rgb() && !self.has_white() {
+            let level = if self.has(Channel::Dimmer) {
+                color.red.max(color.green).max(color.blue).clamp(0., 1.)
+            } else {
+                0.
+            };
+            return [0., 0., 0., level, 0.];
+        }
+        let (rgb, white) = self.split(color, whites);
+        let only = |channel, value| if self.has(channel) { value } else { 0. };
+        [
+            only(Channel::Red, rgb.red),
+            only(Channel::Green, rgb.green),
+            only(Channel::Blue, rgb.blue),
+            white[0],
+            white[1],
+        ]
+    }
+
+    /// The DMX values of one pixel.
+    pub fn render(
+        &self,
+        color: &Color,
+        whites: &WhiteLeds,
+        aim: &Aim,
+        parameters: &Parameters,
+    ) -> Vec<u8> {
+        let (rgb, white) = self.split(color, whites);
+        let intensity = |channel: &Channel| match channel {
+            Channel::Red => Some(rgb.red),
+            Channel::Green => Some(rgb.green),
+            Channel::Blue => Some(rgb.blue),
+            Channel::White | Channel::Warm => Some(white[0]),
+            Channel::Cold => Some(white[1]),
+            Channel::Temperature => Some(white[0] + white[1]),
+            _ => None,
+        };
+        let intensities: Vec<PinValue> = self.channels.iter().filter_map(intensity).collect();
+        // the dimmer takes the brightness, the colors are scaled up
+        let level = if intensities.is_empty() {
+            color.red.max(color.green).max(color.blue)
+        } else {
+            intensities.iter().cloned().fold(0., PinValue::max)
+        };
+        let scale = match (self.has(Channel::Dimmer), level > 0.) {
+            (false, _) => 1.,
+            (true, true) => 1. / level,
+            (true, false) => 0.,
+        };
+        let temperature = if white[0] + white[1] > 0. {
+            white[1] / (white[0] + white[1])
+        } else {
+            0.
+        };
+        let (x, y) = match parameters.position {
+            Some(pos) => ((pos.0 + 1.) / 2., (pos.1 + 1.) / 2.),
+            None => (0.5, 0.5),
+        };
+        let pan = to_dmx16(aim.pan.0 + (aim.pan.1 - aim.pan.0) * x.clamp(0., 1.));
+        let tilt = to_dmx16(aim.tilt.0 + (aim.tilt.1 - aim.tilt.0) * y.clamp(0., 1.));
+        let strobe = if parameters.strobe > 0. {
+            let (slow, fast) = (self.strobe[1] as PinValue, self.strobe[2] as PinValue);
+            (slow + (fast - slow) * parameters.strobe.min(1.)).round() as u8
+        } else {
+            self.strobe[0]
+        };
+        self.channels
+            .iter()
+            .map(|channel| match channel {
+                Channel::Temperature => to_dmx(temperature),
+                Channel::Dimmer => to_dmx(level),
+                Channel::Strobe => strobe,
+                Channel::Pan => pan.0,
+                Channel::PanFine => pan.1,
+                Channel::Tilt => tilt.0,
+                Channel::TiltFine => tilt.1,
+                Channel::Fixed(value) => *value,
+                color => to_dmx(intensity(color).unwrap_or(0.) * scale),
+            })
+            .collect()
+    }
+}
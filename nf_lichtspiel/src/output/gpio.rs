@@ -0,0 +1,145 @@
+//! Drives pins through the GPIO character device, with software PWM
+//! in its own thread.  This needs no daemon or PWM hardware, but the
+//! timing depends on the scheduler, so low resolutions and periods
+//! around 10ms work best.  A resolution of 1 just switches the pins.
+use super::{mean_color, Duty, Frame, OutputBackend, Pin, PinLight};
+use crate::layout::LightId;
+use crate::light::Color;
+use gpio_cdev::{Chip, LineHandle, LineRequestFlags};
+use log::{error, info};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use stoppable_thread::{spawn, StoppableHandle};
+
+fn sleep_until(t: Instant) {
+    if let Some(dur) = t.checked_duration_since(Instant::now()) {
+        thread::sleep(dur);
+    }
+}
+
+/// Sets the line, logging only the first of a series of errors.
+fn set_line(handle: &LineHandle, value: u8, failing: &mut bool) {
+    match handle.set_value(value) {
+        Err(e) if !*failing => {
+            error!("Failed to set GPIO line: {}", e);
+            *failing = true;
+        }
+        Err(_) => (),
+        Ok(()) => *failing = false,
+    }
+}
+
+pub struct GpioPwm {
+    lights: HashMap<LightId, PinLight>,
+    /// The index of each pin in `duty`.
+    pin_index: HashMap<Pin, usize>,
+    /// The duty cycle steps of every line, read by the PWM thread.
+    duty: Arc<Mutex<Vec<u32>>>,
+    resolution: u32,
+    thread: Option<StoppableHandle<()>>,
+}
+
+impl GpioPwm {
+    /// Requests the lines of the lights from the chip, like
+    /// `/dev/gpiochip0`, and starts switching them.
+    pub fn new(
+        chip_path: &str,
+        period: Duration,
+        resolution: u32,
+        lights: Vec<(LightId, PinLight)>,
+    ) -> Result<GpioPwm, String> {
+        let mut chip =
+            Chip::new(chip_path).map_err(|e| format!("Failed to open {}: {}", chip_path, e))?;
+        let lights: HashMap<LightId, PinLight> = lights.into_iter().collect();
+        let mut pin_index = HashMap::new();
+        let mut handles = Vec::new();
+        for pin in lights.values().flat_map(|l| l.pins()) {
+            if pin_index.contains_key(&pin) {
+                continue;
+            }
+            let handle = chip
+                .get_line(pin as u32)
+                .and_then(|line| line.request(LineRequestFlags::OUTPUT, 0, "nightfire"))
+                .map_err(|e| format!("Failed to request GPIO line {}: {}", pin, e))?;
+            pin_index.insert(pin, handles.len());
+            handles.push(handle);
+        }
+        info!("Driving {} GPIO lines on {}.", handles.len(), chip_path);
+        let resolution = resolution.max(1);
+        let duty = Arc::new(Mutex::new(vec![0; handles.len()]));
+        let thread = start_pwm_thread(handles, Arc::clone(&duty), period, resolution);
+        Ok(GpioPwm {
+            lights,
+            pin_index,
+            duty,
+            resolution,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Switches every line on at the start of each period, and off again
+/// once its duty cycle is over.
+fn start_pwm_thread(
+    handles: Vec<LineHandle>,
+    duty: Arc<Mutex<Vec<u32>>>,
+    period: Duration,
+    resolution: u32,
+) -> StoppableHandle<()> {
+    let step = period / resolution;
+    spawn(move |stopped| {
+        let mut failing = false;
+        while !stopped.get() {
+            let t_start = Instant::now();
+            let duty = duty.lock().unwrap().clone();
+            for (handle, d) in handles.iter().zip(&duty) {
+                set_line(handle, (*d > 0) as u8, &mut failing);
+            }
+            let mut switch_offs: Vec<(u32, usize)> = duty
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| **d > 0 && **d < resolution)
+                .map(|(i, d)| (*d, i))
+                .collect();
+            switch_offs.sort_unstable();
+            for (d, i) in switch_offs {
+                sleep_until(t_start + step * d);
+                set_line(&handles[i], 0, &mut failing);
+            }
+            sleep_until(t_start + period);
+        }
+        for handle in &handles {
+            set_line(handle, 0, &mut failing);
+        }
+    })
+}
+
+impl OutputBackend for GpioPwm {
+    fn write_frame(&mut self, frame: &Frame) {
+        let mut duty = self.duty.lock().unwrap();
+        for (id, colors) in frame {
+            if let Some(light) = self.lights.get(id) {
+                for (pin, value) in light.pin_values(&mean_color(colors)).iter() {
+                    duty[self.pin_index[pin]] =
+                        (value.clamp(0., 1.) * self.resolution as f64).round() as u32;
+                }
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            thread.stop().join().unwrap();
+        }
+    }
+
+    fn steps(&self) -> u32 {
+        self.resolution
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.lights.get(id).map(|light| light.duty(color))
+    }
+}
@@ -0,0 +1,255 @@
+//! Color grading with 3D LUTs in the `.cube` format, as exported by
+//! most video and photo editors.  Several LUTs can be loaded and
+//! switched while running, they apply to the whole show.
+use crate::light::cmap::{ColorMap, Parameters};
+use crate::light::{Color, Coordinate, Image, PinValue};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub struct Lut3d {
+    size: usize,
+    domain_min: [PinValue; 3],
+    domain_max: [PinValue; 3],
+    /// Red changes fastest, then green, then blue, like in the file.
+    table: Vec<[PinValue; 3]>,
+}
+
+fn parse_floats(values: &[&str], line: &str) -> Result<Vec<PinValue>, String> {
+    values
+        .iter()
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("Invalid line in LUT: {}", line))
+        })
+        .collect()
+}
+
+fn parse_rgb(values: &[&str], line: &str) -> Result<[PinValue; 3], String> {
+    match parse_floats(values, line)?.as_slice() {
+        [r, g, b] => Ok([*r, *g, *b]),
+        _ => Err(format!("Expected three values in LUT: {}", line)),
+    }
+}
+
+impl Lut3d {
+    pub fn load(path: &Path) -> Result<Lut3d, String> {
+        let text =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {:?}: {}", path, e))?;
+        Lut3d::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Lut3d, String> {
+        let mut size = None;
+        let mut domain_min = [0.; 3];
+        let mut domain_max = [1.; 3];
+        let mut table = vec![];
+        for line in text.lines().map(|l| l.trim()) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                [first, ..] if first.starts_with('#') => (),
+                ["TITLE", ..] => (),
+                ["LUT_3D_SIZE", n] => {
+                    size = Some(n.parse().map_err(|_| format!("Invalid size: {}", n))?)
+                }
+                ["LUT_1D_SIZE", ..] => return Err("1D LUTs are not supported".to_string()),
+                ["DOMAIN_MIN", rgb @ ..] => domain_min = parse_rgb(rgb, line)?,
+                ["DOMAIN_MAX", rgb @ ..] => domain_max = parse_rgb(rgb, line)?,
+                rgb => table.push(parse_rgb(rgb, line)?),
+            }
+        }
+        let size: usize = size.ok_or("LUT_3D_SIZE is missing")?;
+        if size < 2 || table.len() != size.pow(3) {
+            return Err(format!(
+                "Expected {} entries for size {}, got {}",
+                size.pow(3),
+                size,
+                table.len()
+            ));
+        }
+        for (i, axis) in ["red", "green", "blue"].iter().enumerate() {
+            // NaN is not greater either
+            if domain_max[i].partial_cmp(&domain_min[i]) != Some(std::cmp::Ordering::Greater) {
+                return Err(format!(
+                    "DOMAIN_MAX must be above DOMAIN_MIN, but is not for {}",
+                    axis
+                ));
+            }
+        }
+        Ok(Lut3d {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    fn entry(&self, r: usize, g: usize, b: usize) -> [PinValue; 3] {
+        self.table[r + self.size * (g + self.size * b)]
+    }
+
+    /// Looks the color up, interpolated between the eight entries
+    /// around it.
+    pub fn apply(&self, color: &Color) -> Color {
+        let max_index = (self.size - 1) as PinValue;
+        let mut lower = [0; 3];
+        let mut frac = [0.; 3];
+        for (i, value) in [color.red, color.green, color.blue].iter().enumerate() {
+            let range = self.domain_max[i] - self.domain_min[i];
+            let pos = ((value - self.domain_min[i]) / range).clamp(0., 1.) * max_index;
+            lower[i] = (pos.floor() as usize).min(self.size - 2);
+            frac[i] = pos - lower[i] as PinValue;
+        }
+        let mut res = [0.; 3];
+        for corner in 0..8 {
+            let (dr, dg, db) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let weight = [dr, dg, db]
+                .iter()
+                .zip(&frac)
+                .map(|(d, f)| if *d == 1 { *f } else { 1. - f })
+                .product::<PinValue>();
+            let entry = self.entry(lower[0] + dr, lower[1] + dg, lower[2] + db);
+            for (r, e) in res.iter_mut().zip(&entry) {
+                *r += weight * e;
+            }
+        }
+        Color::new(res[0], res[1], res[2])
+    }
+}
+
+/// The loaded LUTs and which one is active, shared between the color
+/// map and the handlers that switch them.
+#[derive(Clone)]
+pub struct LutSelection {
+    luts: Arc<Vec<(String, Lut3d)>>,
+    /// 0 is no LUT, 1 the first one.
+    active: Arc<AtomicUsize>,
+}
+
+impl LutSelection {
+    pub fn new(luts: Vec<(String, Lut3d)>) -> LutSelection {
+        LutSelection {
+            luts: Arc::new(luts),
+            active: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Activates the LUT with the name, or none for `off`.
+    pub fn select(&self, name: &str) -> Result<(), String> {
+        let index = match self.luts.iter().position(|(n, _)| n == name) {
+            Some(i) => i + 1,
+            None if name == "off" => 0,
+            None => return Err(format!("Unknown LUT: {}", name)),
+        };
+        self.active.store(index, Ordering::Relaxed);
+        info!("LUT: {}", name);
+        Ok(())
+    }
+
+    /// Goes to the next LUT, after the last one to none.
+    pub fn next(&self) {
+        let index = (self.active.load(Ordering::Relaxed) + 1) % (self.luts.len() + 1);
+        self.active.store(index, Ordering::Relaxed);
+        match index {
+            0 => info!("LUT: off"),
+            i => info!("LUT: {}", self.luts[i - 1].0),
+        }
+    }
+
+    fn active(&self) -> Option<&Lut3d> {
+        match self.active.load(Ordering::Relaxed) {
+            0 => None,
+            i => self.luts.get(i - 1).map(|(_, lut)| lut),
+        }
+    }
+
+    /// Applies the active LUT to everything the color map returns.
+    pub fn wrap(
+        &self,
+        color_map: Box<dyn ColorMap + Send + Sync>,
+    ) -> Box<dyn ColorMap + Send + Sync> {
+        Box::new(LutColorMap {
+            color_map,
+            luts: self.clone(),
+        })
+    }
+}
+
+struct LutColorMap {
+    color_map: Box<dyn ColorMap + Send + Sync>,
+    luts: LutSelection,
+}
+
+impl ColorMap for LutColorMap {
+    fn get_color(&self, pos: &Coordinate) -> Color {
+        let color = self.color_map.get_color(pos);
+        match self.luts.active() {
+            Some(lut) => lut.apply(&color),
+            None => color,
+        }
+    }
+
+    fn render_image(&self, image: &mut Image) -> bool {
+        if !self.color_map.render_image(image) {
+            return false;
+        }
+        if let Some(lut) = self.luts.active() {
+            image.map_colors(|color| lut.apply(color));
+        }
+        true
+    }
+
+    fn parameters(&self) -> Parameters {
+        self.color_map.parameters()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Size 2, swaps red and blue.
+    const SWAP: &str = "TITLE \"swap\"
+# comment
+LUT_3D_SIZE 2
+DOMAIN_MIN 0 0 0
+DOMAIN_MAX 1 1 1
+0 0 0
+0 0 1
+0 1 0
+0 1 1
+1 0 0
+1 0 1
+1 1 0
+1 1 1
+";
+
+    #[test]
+    fn interpolates_between_entries() {
+        let lut = Lut3d::parse(SWAP).unwrap();
+        let color = lut.apply(&Color::new(0.25, 0.5, 1.));
+        assert_eq!((color.red, color.green, color.blue), (1., 0.5, 0.25));
+    }
+
+    #[test]
+    fn wrong_number_of_entries() {
+        let text = SWAP.replace("LUT_3D_SIZE 2", "LUT_3D_SIZE 3");
+        assert!(Lut3d::parse(&text).is_err());
+        assert!(Lut3d::parse("0 0 0").is_err());
+    }
+
+    #[test]
+    fn empty_domain() {
+        for domain in &[
+            "DOMAIN_MAX 1 0 1",
+            "DOMAIN_MAX 1 1 -1",
+            "DOMAIN_MAX 1 nan 1",
+        ] {
+            let text = SWAP.replace("DOMAIN_MAX 1 1 1", domain);
+            assert!(Lut3d::parse(&text).is_err(), "{}", domain);
+        }
+    }
+}
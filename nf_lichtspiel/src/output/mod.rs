@@ -0,0 +1,214 @@
+//! Everything that puts the light colors out into the world.  Every
+//! protocol is an OutputBackend; the output thread samples the color
+//! map once per frame and hands the colors of all lights to every
+//! configured backend.
+pub mod artnet;
+pub mod calibration;
+pub mod ddp;
+pub mod dmx;
+pub mod fixture;
+pub mod gpio;
+pub mod lut;
+pub mod opc;
+pub mod piblaster;
+pub mod pixels;
+pub mod power;
+pub mod pwm;
+pub mod sacn;
+pub mod scheduler;
+pub mod serial;
+pub mod transfer;
+pub mod white;
+use crate::layout::{Layout, Light, LightId};
+use crate::light::cmap::{ColorMap, Parameters};
+use crate::light::{Color, Image, PinValue};
+use scheduler::{FrameTiming, Schedule, TimingStats};
+use std::time::Instant;
+use stoppable_thread::{spawn, StoppableHandle};
+use white::WhiteLeds;
+
+pub type Pin = i64;
+
+/// The pins of an RGB light, for the outputs that drive pins directly.
+/// A light can have one white pin, or two for a warm and a cold white.
+#[derive(Debug, Clone)]
+pub struct PinLight {
+    pub r_pin: Pin,
+    pub g_pin: Pin,
+    pub b_pin: Pin,
+    pub white_pins: Vec<Pin>,
+    pub whites: WhiteLeds,
+}
+
+impl PinLight {
+    pub fn new(r_pin: Pin, g_pin: Pin, b_pin: Pin) -> PinLight {
+        PinLight {
+            r_pin,
+            g_pin,
+            b_pin,
+            white_pins: vec![],
+            whites: WhiteLeds::default(),
+        }
+    }
+
+    /// Adds white LEDs, on one pin for a single white or two for a
+    /// tunable one.
+    pub fn with_white(mut self, pins: Vec<Pin>, whites: WhiteLeds) -> PinLight {
+        self.white_pins = pins;
+        self.whites = whites;
+        self
+    }
+
+    pub fn pins(&self) -> Vec<Pin> {
+        let mut pins = vec![self.r_pin, self.g_pin, self.b_pin];
+        pins.extend(&self.white_pins);
+        pins
+    }
+
+    /// The brightness of the LEDs for the color, after the white is
+    /// taken out for the white pins.
+    pub fn duty(&self, color: &Color) -> Duty {
+        let (rgb, whites) = if self.white_pins.is_empty() {
+            (*color, [0.; 2])
+        } else {
+            self.whites.split(color, true)
+        };
+        [rgb.red, rgb.green, rgb.blue, whites[0], whites[1]]
+    }
+
+    /// The pins with the matching color values.
+    pub fn pin_values(&self, color: &Color) -> Vec<(Pin, PinValue)> {
+        let duty = self.duty(color);
+        let mut values = vec![
+            (self.r_pin, duty[0]),
+            (self.g_pin, duty[1]),
+            (self.b_pin, duty[2]),
+        ];
+        values.extend(self.white_pins.iter().copied().zip(duty[3..].iter().copied()));
+        values
+    }
+}
+
+/// The colors of all lights at one point in time, with a color for
+/// every pixel of a light.
+pub type Frame = [(LightId, Vec<Color>)];
+
+/// How bright the LEDs of a pixel are: r, g, b, then the white or warm
+/// white and the cold white.
+pub type Duty = [PinValue; 5];
+
+/// The mean color of the pixels, for outputs that drive a light as a
+/// single lamp.
+pub fn mean_color(colors: &[Color]) -> Color {
+    let n = colors.len().max(1) as PinValue;
+    Color::new(
+        colors.iter().map(|c| c.red).sum::<PinValue>() / n,
+        colors.iter().map(|c| c.green).sum::<PinValue>() / n,
+        colors.iter().map(|c| c.blue).sum::<PinValue>() / n,
+    )
+}
+
+/// The colors of all pixels of a light.  A matrix gets an image from
+/// the color map if it draws one, everything else is sampled at the
+/// pixel positions.
+pub fn light_colors(light: &Light, color_map: &dyn ColorMap) -> Vec<Color> {
+    if let Some(matrix) = &light.matrix {
+        let mut image = Image::new(matrix.width, matrix.height);
+        if color_map.render_image(&mut image) {
+            return (0..matrix.n_pixels())
+                .map(|n| {
+                    let (x, y) = matrix.pixel(n);
+                    image.get(x, y)
+                })
+                .collect();
+        }
+    }
+    light.pixels.iter().map(|p| color_map.get_color(p)).collect()
+}
+
+pub trait OutputBackend: Send {
+    /// Writes out the colors.  Lights that the backend doesn't know
+    /// are ignored.
+    fn write_frame(&mut self, frame: &Frame);
+
+    /// Called before every frame with the values besides the colors,
+    /// for backends that drive fixtures with channels for them.
+    fn set_parameters(&mut self, _parameters: &Parameters) {}
+
+    /// Called when the output stops, to switch off the lights or
+    /// close connections cleanly.
+    fn shutdown(&mut self) {}
+
+    /// The number of steps from off to full brightness, which
+    /// dithering rounds to.  8 bits for DMX and pixel protocols.
+    fn steps(&self) -> u32 {
+        255
+    }
+
+    /// How bright the LEDs of the light are for a pixel of the color,
+    /// after everything the backend does to it, like taking the white
+    /// out for white LEDs.  None if the backend doesn't drive the
+    /// light.  For the power estimate.
+    fn duty(&self, _id: &LightId, _color: &Color) -> Option<Duty> {
+        None
+    }
+}
+
+/// Starts reading from the color map at the positions of all pixels
+/// and writing it to all backends.  The loop runs at the tick rate of
+/// the timing and computes new frames at its frame rate, on the ticks
+/// in between the last frame is sent again or blended into.
+pub fn start_output_thread(
+    mut backends: Vec<Box<dyn OutputBackend>>,
+    layout: Layout,
+    color_map: Box<dyn ColorMap + Send + Sync>,
+    timing: FrameTiming,
+) -> StoppableHandle<Vec<Box<dyn OutputBackend>>> {
+    spawn(move |stopped| {
+        let compute = || -> Vec<(LightId, Vec<Color>)> {
+            layout
+                .all()
+                .iter()
+                .map(|light| (light.id.clone(), light_colors(light, color_map.as_ref())))
+                .collect()
+        };
+        let mut ticks = Schedule::new(timing.tick_fps);
+        let mut frames = Schedule::new(timing.fps);
+        let tolerance = ticks.period() / 2;
+        let interpolate = timing.interpolate && timing.tick_fps > timing.fps;
+        let mut stats = timing
+            .stats
+            .map(|interval| TimingStats::new("Output loop", interval));
+        let mut previous = None;
+        let mut current = compute();
+        let mut computed = Instant::now();
+        while !stopped.get() {
+            let (late, skipped) = ticks.wait();
+            let start = Instant::now();
+            if frames.due(start, tolerance) {
+                previous = Some(std::mem::replace(&mut current, compute()));
+                computed = start;
+            }
+            let blended = match &previous {
+                Some(previous) if interpolate => {
+                    let t = start.duration_since(computed).as_secs_f64() * timing.fps;
+                    Some(scheduler::interpolate(previous, &current, t.min(1.)))
+                }
+                _ => None,
+            };
+            let frame = blended.as_ref().unwrap_or(&current);
+            let parameters = color_map.parameters();
+            for backend in backends.iter_mut() {
+                backend.set_parameters(&parameters);
+                backend.write_frame(frame);
+            }
+            if let Some(stats) = &mut stats {
+                stats.record(late, start.elapsed(), skipped);
+            }
+        }
+        for backend in backends.iter_mut() {
+            backend.shutdown();
+        }
+        backends
+    })
+}
@@ -0,0 +1,140 @@
+//! Open Pixel Control output, as spoken by fadecandy servers and many
+//! ESP based controllers.  Messages go over TCP, the connection is
+//! reestablished if it breaks.
+use super::pixels::PixelMapping;
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::Color;
+use log::{info, warn};
+use std::io::Write;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+pub const OPC_PORT: u16 = 7890;
+const CMD_SET_PIXELS: u8 = 0;
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(200);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A set pixel colors message.
+pub fn set_pixels_message(channel: u8, data: &[u8]) -> Vec<u8> {
+    let mut buf = vec![channel, CMD_SET_PIXELS];
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub struct Opc {
+    address: SocketAddr,
+    /// 0 sends to all channels of the server.
+    channel: u8,
+    mapping: PixelMapping,
+    stream: Option<TcpStream>,
+    t_last_attempt: Option<Instant>,
+}
+
+impl Opc {
+    pub fn new(address: &str, channel: u8, mapping: PixelMapping) -> Result<Opc, String> {
+        let address = address
+            .to_socket_addrs()
+            .map_err(|e| format!("Invalid OPC address {}: {}", address, e))?
+            .next()
+            .ok_or_else(|| format!("OPC address {} not found", address))?;
+        Ok(Opc {
+            address,
+            channel,
+            mapping,
+            stream: None,
+            t_last_attempt: None,
+        })
+    }
+
+    /// Connects if not connected, but not more often than every
+    /// RECONNECT_INTERVAL, so an offline server doesn't slow down the
+    /// other outputs.
+    fn connection(&mut self) -> Option<&mut TcpStream> {
+        let may_retry = self
+            .t_last_attempt
+            .is_none_or(|t| t.elapsed() > RECONNECT_INTERVAL);
+        if self.stream.is_none() && may_retry {
+            self.t_last_attempt = Some(Instant::now());
+            match TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    info!("Connected to OPC server at {}.", self.address);
+                    stream.set_nodelay(true).ok();
+                    stream.set_write_timeout(Some(CONNECT_TIMEOUT)).ok();
+                    self.stream = Some(stream);
+                }
+                Err(e) => warn!("Failed to connect to OPC server at {}: {}", self.address, e),
+            }
+        }
+        self.stream.as_mut()
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        let message = set_pixels_message(self.channel, data);
+        if let Some(stream) = self.connection() {
+            if let Err(e) = stream.write_all(&message) {
+                warn!("Lost connection to OPC server: {}", e);
+                self.stream = None;
+            }
+        }
+    }
+}
+
+impl OutputBackend for Opc {
+    fn write_frame(&mut self, frame: &Frame) {
+        let data = self.mapping.render(frame);
+        self.send(&data);
+    }
+
+    fn shutdown(&mut self) {
+        self.send(&vec![0; self.mapping.n_pixels() * 3]);
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.mapping.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::pixels::PixelLight;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    #[test]
+    fn set_pixels_header() {
+        let message = set_pixels_message(2, &[1, 2, 3]);
+        assert_eq!(message, [2, 0, 0, 3, 1, 2, 3]);
+        let message = set_pixels_message(0, &[0; 300 * 3]);
+        assert_eq!(message[2..4], [0x03, 0x84]);
+        assert_eq!(message.len(), 4 + 900);
+    }
+
+    #[test]
+    fn address_without_port_is_an_error() {
+        assert!(Opc::new("127.0.0.1", 0, PixelMapping::new(vec![])).is_err());
+    }
+
+    #[test]
+    fn sends_to_a_local_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let light = PixelLight { start: 1, count: 2 };
+        let mut opc = Opc::new(
+            &listener.local_addr().unwrap().to_string(),
+            1,
+            PixelMapping::new(vec![(LightId::get("Strip"), light)]),
+        )
+        .unwrap();
+        let frame = vec![(LightId::get("Strip"), vec![Color::new(0., 0., 1.); 2])];
+        opc.write_frame(&frame);
+        let (mut stream, _) = listener.accept().unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let mut message = [0u8; 13];
+        stream.read_exact(&mut message).unwrap();
+        assert_eq!(message, [1, 0, 0, 9, 0, 0, 0, 0, 0, 255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,131 @@
+use super::{mean_color, Duty, Frame, OutputBackend, Pin, PinLight};
+use crate::layout::LightId;
+use crate::light::{Color, PinValue};
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::vec::Vec;
+
+struct PinModel {
+    pin_values: HashMap<Pin, PinValue>,
+    outfile: File,
+    /// Set while writing fails, so the error is only logged once.
+    failing: bool,
+}
+
+/// The PinModel models the pins that we have and the actual values on
+/// each pin.  It supports setting individual pin values.  Writing
+/// needs to be triggered extra, so multiple pins can be set before
+/// all data is written out.
+impl PinModel {
+    fn new(pins: Vec<Pin>, path: &str) -> Result<PinModel, String> {
+        let outfile = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+        let mut model = PinModel {
+            pin_values: HashMap::new(),
+            outfile,
+            failing: false,
+        };
+        // initialize to zero
+        for pin in pins {
+            let value = 0.0;
+            model.set_pin(pin, value);
+        }
+        model.write_out();
+        Ok(model)
+    }
+
+    fn set_pin(&mut self, pin: Pin, value: PinValue) {
+        // don't set if value is identical to current value
+        match self.pin_values.get(&pin) {
+            Some(curr_val) => {
+                if value == *curr_val {
+                    return;
+                }
+            }
+            None => (),
+        }
+        self.pin_values.insert(pin, value);
+        let s = format!("{}={}\n", pin, value);
+        let s = s.as_bytes();
+        let result = self.outfile.write_all(s);
+        self.report(result);
+    }
+
+    fn write_out(&mut self) {
+        let result = self.outfile.sync_data();
+        self.report(result);
+    }
+
+    fn report(&mut self, result: std::io::Result<()>) {
+        match result {
+            Err(e) if !self.failing => {
+                error!("Failed to write to pi-blaster: {}", e);
+                self.failing = true;
+            }
+            Ok(()) if self.failing => {
+                info!("Writing to pi-blaster works again.");
+                self.failing = false;
+            }
+            _ => (),
+        }
+    }
+}
+
+pub struct Lights {
+    pin_model: PinModel,
+    light_map: HashMap<LightId, PinLight>,
+}
+
+impl Lights {
+    pub fn new(lights: Vec<(LightId, PinLight)>, path: &str) -> Result<Lights, String> {
+        let mut map = HashMap::new();
+        let mut pins = Vec::new();
+        for (light_id, light) in lights {
+            pins.extend(light.pins());
+            map.insert(light_id, light);
+        }
+        let pin_model = PinModel::new(pins, path)?;
+        Ok(Lights {
+            pin_model: pin_model,
+            light_map: map,
+        })
+    }
+
+    fn set_light(&mut self, id: &LightId, color: &Color) {
+        if let Some(light) = self.light_map.get(id) {
+            for (pin, value) in light.pin_values(color) {
+                self.pin_model.set_pin(pin, value);
+            }
+        }
+    }
+}
+
+impl OutputBackend for Lights {
+    fn write_frame(&mut self, frame: &Frame) {
+        for (id, colors) in frame {
+            self.set_light(id, &mean_color(colors));
+        }
+        self.pin_model.write_out();
+    }
+
+    fn shutdown(&mut self) {
+        let ids: Vec<LightId> = self.light_map.keys().cloned().collect();
+        for id in ids {
+            self.set_light(&id, &Color::new(0., 0., 0.));
+        }
+        self.pin_model.write_out();
+    }
+
+    /// The default resolution of pi-blaster.
+    fn steps(&self) -> u32 {
+        1000
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.light_map.get(id).map(|light| light.duty(color))
+    }
+}
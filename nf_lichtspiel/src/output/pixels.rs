@@ -0,0 +1,105 @@
+//! Maps lights to ranges of pixels on addressable strips, shared by
+//! the pixel based outputs.
+use super::{Duty, Frame};
+use crate::layout::LightId;
+use crate::light::Color;
+use std::str::FromStr;
+
+/// The order in which a strip expects the color bytes of a pixel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ColorOrder([usize; 3]);
+
+impl FromStr for ColorOrder {
+    type Err = String;
+
+    /// Parses orders like `rgb` or `grb`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let indices: Vec<usize> = s
+            .chars()
+            .filter_map(|c| "rgb".find(c.to_ascii_lowercase()))
+            .collect();
+        let mut sorted = indices.clone();
+        sorted.sort_unstable();
+        if sorted != [0, 1, 2] {
+            return Err(format!("Invalid color order: {}", s));
+        }
+        Ok(ColorOrder([indices[0], indices[1], indices[2]]))
+    }
+}
+
+impl Default for ColorOrder {
+    fn default() -> Self {
+        ColorOrder([0, 1, 2])
+    }
+}
+
+impl ColorOrder {
+    /// Reorders rendered r, g, b bytes in place.
+    pub fn apply(&self, data: &mut [u8]) {
+        for pixel in data.chunks_mut(3) {
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            for (i, c) in self.0.iter().enumerate() {
+                pixel[i] = rgb[*c];
+            }
+        }
+    }
+}
+
+/// A light on `count` LEDs, starting at LED `start`.
+#[derive(Debug, Copy, Clone)]
+pub struct PixelLight {
+    pub start: usize,
+    pub count: usize,
+}
+
+pub struct PixelMapping {
+    lights: Vec<(LightId, PixelLight)>,
+    n_pixels: usize,
+}
+
+fn to_byte(value: f64) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+impl PixelMapping {
+    pub fn new(lights: Vec<(LightId, PixelLight)>) -> PixelMapping {
+        let n_pixels = lights
+            .iter()
+            .map(|(_, l)| l.start + l.count)
+            .max()
+            .unwrap_or(0);
+        PixelMapping { lights, n_pixels }
+    }
+
+    /// The length of the strip, up to the last used pixel.
+    pub fn n_pixels(&self) -> usize {
+        self.n_pixels
+    }
+
+    /// The brightness of the LEDs of a pixel of the light, None if the
+    /// light isn't mapped.
+    pub fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.lights
+            .iter()
+            .find(|(l_id, _)| l_id == id)
+            .map(|_| [color.red, color.green, color.blue, 0., 0.])
+    }
+
+    /// The pixels as r, g, b bytes.  Pixels of no light are black.
+    pub fn render(&self, frame: &Frame) -> Vec<u8> {
+        let mut data = vec![0; self.n_pixels * 3];
+        for (id, colors) in frame {
+            for (_, light) in self.lights.iter().filter(|(l_id, _)| l_id == id) {
+                // stretch the light's pixels over the LEDs, so a lamp
+                // can fill a whole strip too
+                for i in 0..light.count {
+                    let color = &colors[i * colors.len() / light.count];
+                    let rgb = [to_byte(color.red), to_byte(color.green), to_byte(color.blue)];
+                    let pixel = light.start + i;
+                    data[pixel * 3..pixel * 3 + 3].copy_from_slice(&rgb);
+                }
+            }
+        }
+        data
+    }
+}
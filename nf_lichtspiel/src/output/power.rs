@@ -0,0 +1,260 @@
+//! Keeps the whole installation within the budget of its power supply.
+//! The current of every frame is estimated from the brightness of the
+//! LEDs, as the outputs would drive them, and when it is over the
+//! budget all lights are dimmed evenly until it fits.
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::{Layout, LightId};
+use crate::light::cmap::Parameters;
+use crate::light::color::ColorsExt;
+use crate::light::{Color, PinValue};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// The current of a light at full brightness, in mA for each LED of a
+/// single pixel, in the order of Duty.
+pub type Current = [f64; 5];
+
+pub struct PowerLimiter {
+    backends: Vec<Box<dyn OutputBackend>>,
+    /// The most the lights may draw, in mA.
+    budget: f64,
+    currents: HashMap<LightId, Current>,
+    /// Seconds to get back to full brightness after a peak.
+    release: f64,
+    scale: PinValue,
+    last: Option<Instant>,
+    /// The highest estimate while limiting, for the report.
+    peak: f64,
+}
+
+impl PowerLimiter {
+    /// Limits the frames for all backends.  The currents of the lights
+    /// are looked up in `currents`, lights that aren't in it draw the
+    /// default current.
+    pub fn new(
+        backends: Vec<Box<dyn OutputBackend>>,
+        layout: &Layout,
+        budget: f64,
+        default_current: Current,
+        currents: HashMap<LightId, Current>,
+        release: f64,
+    ) -> PowerLimiter {
+        let currents = layout
+            .all()
+            .iter()
+            .map(|light| {
+                let current = currents.get(&light.id).copied().unwrap_or(default_current);
+                (light.id.clone(), current)
+            })
+            .collect();
+        PowerLimiter {
+            backends,
+            budget,
+            currents,
+            release,
+            scale: 1.,
+            last: None,
+            peak: 0.,
+        }
+    }
+
+    /// The current in mA the frame would draw with all colors scaled.
+    fn estimate(&self, frame: &Frame, scale: PinValue) -> f64 {
+        let mut total = 0.;
+        for (id, colors) in frame.iter() {
+            let current = match self.currents.get(id) {
+                Some(current) => current,
+                None => continue,
+            };
+            for color in colors.iter() {
+                if let Some(duty) = self.duty(id, &color.mask(scale)) {
+                    total += duty
+                        .iter()
+                        .zip(current)
+                        .map(|(d, c)| d.clamp(0., 1.) * c)
+                        .sum::<f64>();
+                }
+            }
+        }
+        total
+    }
+
+    /// The highest scale at which the frame is within the budget.
+    fn fitting_scale(&self, frame: &Frame) -> PinValue {
+        if self.estimate(frame, 1.) <= self.budget {
+            return 1.;
+        }
+        // the curves only rise, so the scale can be searched for
+        let (mut low, mut high) = (0., 1.);
+        for _ in 0..12 {
+            let mid = (low + high) / 2.;
+            if self.estimate(frame, mid) <= self.budget {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    /// Dims at once when the frame is too bright, so the supply is
+    /// never over its budget, and comes back slowly after a peak.
+    fn update_scale(&mut self, frame: &Frame) {
+        let now = Instant::now();
+        let dt = self
+            .last
+            .map(|last| now.duration_since(last).as_secs_f64())
+            .unwrap_or(0.);
+        self.last = Some(now);
+        let target = self.fitting_scale(frame);
+        let was_limiting = self.scale < 1.;
+        self.scale = if target < self.scale || self.release <= 0. {
+            target
+        } else {
+            (self.scale + dt / self.release).min(target)
+        };
+        if self.scale < 1. {
+            let estimate = self.estimate(frame, 1.);
+            if !was_limiting {
+                warn!(
+                    "Power limit: {:.0} mA over the budget of {:.0} mA, dimming to {:.0}%",
+                    estimate - self.budget,
+                    self.budget,
+                    self.scale * 100.
+                );
+                self.peak = 0.;
+            }
+            self.peak = self.peak.max(estimate);
+        } else if was_limiting {
+            info!("Power limit released, the peak was {:.0} mA", self.peak);
+        }
+    }
+}
+
+impl OutputBackend for PowerLimiter {
+    fn write_frame(&mut self, frame: &Frame) {
+        self.update_scale(frame);
+        if self.scale < 1. {
+            let scaled: Vec<(LightId, Vec<Color>)> = frame
+                .iter()
+                .map(|(id, colors)| {
+                    (
+                        id.clone(),
+                        colors.iter().map(|c| c.mask(self.scale)).collect(),
+                    )
+                })
+                .collect();
+            for backend in self.backends.iter_mut() {
+                backend.write_frame(&scaled);
+            }
+        } else {
+            for backend in self.backends.iter_mut() {
+                backend.write_frame(frame);
+            }
+        }
+    }
+
+    fn set_parameters(&mut self, parameters: &Parameters) {
+        for backend in self.backends.iter_mut() {
+            backend.set_parameters(parameters);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        for backend in self.backends.iter_mut() {
+            backend.shutdown();
+        }
+    }
+
+    /// As on the first output that drives the light.  Lights on no
+    /// output draw nothing.
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.backends.iter().find_map(|b| b.duty(id, color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{Light, Shape};
+    use crate::light::Coordinate;
+    use crate::output::transfer::{Curve, Transfer};
+    use crate::output::white::WhiteLeds;
+    use crate::output::PinLight;
+
+    /// A lamp on pins, like the pin outputs drive it.
+    struct Lamp(PinLight);
+
+    impl OutputBackend for Lamp {
+        fn write_frame(&mut self, _frame: &Frame) {}
+
+        fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+            if *id == LightId::get("Lamp") {
+                Some(self.0.duty(color))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn layout() -> Layout {
+        Layout::new(vec![
+            Light::new("Lamp", Shape::Point(Coordinate(0., 0.)), 1),
+            Light::new("Other", Shape::Point(Coordinate(0., 0.)), 1),
+        ])
+    }
+
+    fn limiter(backend: Box<dyn OutputBackend>, budget: f64) -> PowerLimiter {
+        let current = [20., 20., 20., 50., 50.];
+        PowerLimiter::new(
+            vec![backend],
+            &layout(),
+            budget,
+            current,
+            HashMap::new(),
+            0.,
+        )
+    }
+
+    fn frame(id: &str, color: Color) -> Vec<(LightId, Vec<Color>)> {
+        vec![(LightId::get(id), vec![color])]
+    }
+
+    #[test]
+    fn white_goes_to_the_white_leds() {
+        let rgb = limiter(Box::new(Lamp(PinLight::new(0, 1, 2))), 1000.);
+        assert_eq!(rgb.estimate(&frame("Lamp", Color::white()), 1.), 60.);
+        let rgbw = PinLight::new(0, 1, 2).with_white(vec![3], WhiteLeds::default());
+        let rgbw = limiter(Box::new(Lamp(rgbw)), 1000.);
+        assert_eq!(rgbw.estimate(&frame("Lamp", Color::white()), 1.), 50.);
+        let red = Color::new(1., 0., 0.);
+        assert_eq!(rgbw.estimate(&frame("Lamp", red), 1.), 20.);
+    }
+
+    #[test]
+    fn with_the_curve_of_the_output() {
+        let lamp = Box::new(Lamp(PinLight::new(0, 1, 2)));
+        let transfer = Transfer::new(lamp, Curve::Gamma(2.), &layout(), false);
+        let limiter = limiter(Box::new(transfer), 1000.);
+        let grey = Color::new(0.5, 0.5, 0.5);
+        assert_eq!(limiter.estimate(&frame("Lamp", grey), 1.), 15.);
+        assert_eq!(limiter.estimate(&frame("Lamp", Color::white()), 0.5), 15.);
+    }
+
+    #[test]
+    fn lights_on_no_output_draw_nothing() {
+        let limiter = limiter(Box::new(Lamp(PinLight::new(0, 1, 2))), 1000.);
+        assert_eq!(limiter.estimate(&frame("Other", Color::white()), 1.), 0.);
+    }
+
+    #[test]
+    fn dims_to_the_budget() {
+        let mut limiter = limiter(Box::new(Lamp(PinLight::new(0, 1, 2))), 30.);
+        limiter.write_frame(&frame("Lamp", Color::white()));
+        assert!((limiter.scale - 0.5).abs() < 0.001);
+        assert!(limiter.estimate(&frame("Lamp", Color::white()), limiter.scale) <= 30.);
+        limiter.write_frame(&frame("Lamp", Color::new(0.25, 0.25, 0.25)));
+        assert_eq!(limiter.scale, 1.);
+    }
+}
@@ -0,0 +1,252 @@
+//! Drives pins through the kernel PWM interface in `/sys/class/pwm`,
+//! without a daemon like pi-blaster.  The hardware decides which
+//! channels exist, e.g. the two PWM channels of a Raspberry Pi or the
+//! 16 of a PCA9685 board.
+use super::{mean_color, Duty, Frame, OutputBackend, Pin, PinLight};
+use crate::layout::LightId;
+use crate::light::{Color, PinValue};
+use log::{error, info};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the kernel puts the PWM chips, the default for `root`.
+pub const SYSFS_PWM: &str = "/sys/class/pwm";
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn write_value(path: &Path, value: impl ToString) -> Result<(), String> {
+    fs::write(path, value.to_string()).map_err(|e| format!("Failed to write {:?}: {}", path, e))
+}
+
+pub struct SysfsPwm {
+    chip_dir: PathBuf,
+    /// The PWM period in nanoseconds.
+    period: u64,
+    /// The number of duty cycle steps.
+    resolution: u32,
+    lights: HashMap<LightId, PinLight>,
+    /// The last step written per channel, to skip writing unchanged
+    /// values.
+    steps: HashMap<Pin, u32>,
+    /// Set while writing fails, so the error is only logged once.
+    failing: bool,
+}
+
+impl SysfsPwm {
+    /// Exports and enables the channels of the lights on the chip,
+    /// `root` is usually SYSFS_PWM.
+    pub fn new(
+        root: &Path,
+        chip: u32,
+        period: u64,
+        resolution: u32,
+        lights: Vec<(LightId, PinLight)>,
+    ) -> Result<SysfsPwm, String> {
+        let chip_dir = root.join(format!("pwmchip{}", chip));
+        if !chip_dir.is_dir() {
+            return Err(format!("PWM chip {:?} not found", chip_dir));
+        }
+        let mut pwm = SysfsPwm {
+            chip_dir,
+            period,
+            resolution: resolution.max(1),
+            lights: lights.into_iter().collect(),
+            steps: HashMap::new(),
+            failing: false,
+        };
+        let channels: Vec<Pin> = pwm.lights.values().flat_map(|l| l.pins()).collect();
+        for channel in channels {
+            pwm.export(channel)?;
+        }
+        info!(
+            "Driving {} PWM channels on {:?}.",
+            pwm.steps.len(),
+            pwm.chip_dir
+        );
+        Ok(pwm)
+    }
+
+    fn channel_dir(&self, channel: Pin) -> PathBuf {
+        self.chip_dir.join(format!("pwm{}", channel))
+    }
+
+    fn export(&mut self, channel: Pin) -> Result<(), String> {
+        let dir = self.channel_dir(channel);
+        if !dir.is_dir() {
+            write_value(&self.chip_dir.join("export"), channel)?;
+            // the directory shows up asynchronously
+            let t_start = Instant::now();
+            while !dir.is_dir() {
+                if t_start.elapsed() > EXPORT_TIMEOUT {
+                    return Err(format!("PWM channel {:?} did not appear", dir));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        // the duty cycle may not be longer than the period, so it is
+        // reset before the period is changed
+        write_value(&dir.join("duty_cycle"), 0).ok();
+        write_value(&dir.join("period"), self.period)?;
+        write_value(&dir.join("duty_cycle"), 0)?;
+        write_value(&dir.join("enable"), 1)?;
+        self.steps.insert(channel, 0);
+        Ok(())
+    }
+
+    fn set_channel(&mut self, channel: Pin, value: PinValue) {
+        let step = (value.clamp(0., 1.) * self.resolution as f64).round() as u32;
+        if self.steps.get(&channel) == Some(&step) {
+            return;
+        }
+        let duty = self.period * step as u64 / self.resolution as u64;
+        match write_value(&self.channel_dir(channel).join("duty_cycle"), duty) {
+            Ok(()) => {
+                self.steps.insert(channel, step);
+                if self.failing {
+                    info!("Writing PWM values works again.");
+                    self.failing = false;
+                }
+            }
+            Err(e) if !self.failing => {
+                error!("{}", e);
+                self.failing = true;
+            }
+            Err(_) => (),
+        }
+    }
+}
+
+impl OutputBackend for SysfsPwm {
+    fn write_frame(&mut self, frame: &Frame) {
+        for (id, colors) in frame {
+            let values = self
+                .lights
+                .get(id)
+                .map(|l| l.pin_values(&mean_color(colors)));
+            for (channel, value) in values.unwrap_or_default() {
+                self.set_channel(channel, value);
+            }
+        }
+    }
+
+    fn shutdown(&mut self) {
+        let channels: Vec<Pin> = self.steps.keys().copied().collect();
+        for channel in channels {
+            self.set_channel(channel, 0.);
+            write_value(&self.channel_dir(channel).join("enable"), 0).ok();
+        }
+    }
+
+    fn steps(&self) -> u32 {
+        self.resolution
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.lights.get(id).map(|light| light.duty(color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// An empty directory to stand in for `/sys/class/pwm`, with a
+    /// `pwmchip0` in it.
+    fn fake_sysfs(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("nightfire-pwm-{}-{}", process::id(), name));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("pwmchip0")).unwrap();
+        root
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn exported(root: &Path, channels: &[Pin]) {
+        for channel in channels {
+            fs::create_dir(root.join(format!("pwmchip0/pwm{}", channel))).unwrap();
+        }
+    }
+
+    fn lamp() -> Vec<(LightId, PinLight)> {
+        vec![(LightId::get("Lamp"), PinLight::new(0, 1, 2))]
+    }
+
+    #[test]
+    fn missing_chip() {
+        let root = fake_sysfs("missing");
+        assert!(SysfsPwm::new(&root, 1, 1000, 10, lamp()).is_err());
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn exports_and_enables_channels() {
+        let root = fake_sysfs("export");
+        // the kernel creates the channel directory after the export
+        let chip = root.join("pwmchip0");
+        let kernel = thread::spawn(move || {
+            let t_start = Instant::now();
+            while t_start.elapsed() < EXPORT_TIMEOUT {
+                if let Ok(channel) = fs::read_to_string(chip.join("export")) {
+                    fs::create_dir(chip.join(format!("pwm{}", channel))).unwrap();
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let lights = vec![(LightId::get("Lamp"), PinLight::new(2, 2, 2))];
+        let pwm = SysfsPwm::new(&root, 0, 1000, 10, lights).unwrap();
+        kernel.join().unwrap();
+        assert_eq!(read(root.join("pwmchip0/export")), "2");
+        assert_eq!(read(root.join("pwmchip0/pwm2/period")), "1000");
+        assert_eq!(read(root.join("pwmchip0/pwm2/duty_cycle")), "0");
+        assert_eq!(read(root.join("pwmchip0/pwm2/enable")), "1");
+        assert!(!pwm.failing);
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn writes_duty_cycles_in_steps() {
+        let root = fake_sysfs("duty");
+        exported(&root, &[0, 1, 2]);
+        let mut pwm = SysfsPwm::new(&root, 0, 1000, 10, lamp()).unwrap();
+        assert!(!root.join("pwmchip0/export").exists());
+        let frame = vec![(LightId::get("Lamp"), vec![Color::new(1., 0.5, 0.04)])];
+        pwm.write_frame(&frame);
+        assert_eq!(read(root.join("pwmchip0/pwm0/duty_cycle")), "1000");
+        assert_eq!(read(root.join("pwmchip0/pwm1/duty_cycle")), "500");
+        // rounded down to step 0
+        assert_eq!(read(root.join("pwmchip0/pwm2/duty_cycle")), "0");
+        pwm.shutdown();
+        assert_eq!(read(root.join("pwmchip0/pwm0/duty_cycle")), "0");
+        assert_eq!(read(root.join("pwmchip0/pwm0/enable")), "0");
+        fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn failing_while_writes_fail() {
+        let root = fake_sysfs("failing");
+        exported(&root, &[0, 1, 2]);
+        let mut pwm = SysfsPwm::new(&root, 0, 1000, 10, lamp()).unwrap();
+        let frame = |v| vec![(LightId::get("Lamp"), vec![Color::new(v, 0., 0.)])];
+        // a directory in place of the file can't be written, not even
+        // by root
+        let duty_cycle = root.join("pwmchip0/pwm0/duty_cycle");
+        fs::remove_file(&duty_cycle).unwrap();
+        fs::create_dir(&duty_cycle).unwrap();
+        pwm.write_frame(&frame(1.));
+        assert!(pwm.failing);
+        assert_eq!(pwm.steps[&0], 0);
+        fs::remove_dir(&duty_cycle).unwrap();
+        pwm.write_frame(&frame(1.));
+        assert!(!pwm.failing);
+        assert_eq!(pwm.steps[&0], 10);
+        assert_eq!(read(duty_cycle), "1000");
+        fs::remove_dir_all(root).ok();
+    }
+}
@@ -0,0 +1,278 @@
+//! sACN (E1.31) output: DMX over UDP, multicast to the address of
+//! each universe, or unicast to a single receiver.
+use super::dmx::{DmxMapping, Universe};
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::cmap::Parameters;
+use crate::light::Color;
+use log::{info, warn};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+pub const SACN_PORT: u16 = 5568;
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+/// Receivers stop waiting for a source after three terminated packets.
+const TERMINATION_PACKETS: usize = 3;
+
+/// Settings that identify this source to the receivers.
+pub struct SacnSource {
+    /// Shown on consoles, at most 63 bytes are sent.
+    pub name: String,
+    /// A unique id for this source, should stay the same across restarts.
+    pub cid: [u8; 16],
+    /// 0 to 200, receivers use the source with the highest priority.
+    pub priority: u8,
+}
+
+/// Parses a CID written like a UUID, the dashes are optional.
+pub fn parse_cid(s: &str) -> Result<[u8; 16], String> {
+    let hex: Vec<char> = s.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 {
+        return Err(format!("Invalid CID: {}", s));
+    }
+    let mut cid = [0u8; 16];
+    for (i, byte) in cid.iter_mut().enumerate() {
+        let digits: String = hex[2 * i..2 * i + 2].iter().collect();
+        *byte = u8::from_str_radix(&digits, 16).map_err(|_| format!("Invalid CID: {}", s))?;
+    }
+    Ok(cid)
+}
+
+/// The multicast address that receivers of a universe listen on.
+pub fn multicast_address(universe: u16) -> Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, hi, lo)
+}
+
+fn flags_and_length(length: usize) -> [u8; 2] {
+    (0x7000 | length as u16).to_be_bytes()
+}
+
+/// An E1.31 data packet with all 512 slots.
+pub fn data_packet(
+    source: &SacnSource,
+    sequence: u8,
+    options: u8,
+    universe: u16,
+    data: &Universe,
+) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(126 + data.len());
+    // root layer
+    buf.extend_from_slice(&0x0010u16.to_be_bytes());
+    buf.extend_from_slice(&0x0000u16.to_be_bytes());
+    buf.extend_from_slice(ACN_PACKET_IDENTIFIER);
+    let len = 126 + data.len();
+    buf.extend_from_slice(&flags_and_length(len - 16));
+    buf.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+    buf.extend_from_slice(&source.cid);
+    // framing layer
+    buf.extend_from_slice(&flags_and_length(len - 38));
+    buf.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+    let mut name = [0u8; 64];
+    let name_bytes = source.name.as_bytes();
+    let n = name_bytes.len().min(63);
+    name[..n].copy_from_slice(&name_bytes[..n]);
+    buf.extend_from_slice(&name);
+    buf.push(source.priority);
+    buf.extend_from_slice(&0u16.to_be_bytes()); // no synchronization
+    buf.push(sequence);
+    buf.push(options);
+    buf.extend_from_slice(&universe.to_be_bytes());
+    // DMP layer
+    buf.extend_from_slice(&flags_and_length(len - 115));
+    buf.push(VECTOR_DMP_SET_PROPERTY);
+    buf.push(0xa1); // address and data type
+    buf.extend_from_slice(&0u16.to_be_bytes()); // first property address
+    buf.extend_from_slice(&1u16.to_be_bytes()); // address increment
+    buf.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+    buf.push(0); // DMX start code
+    buf.extend_from_slice(data);
+    buf
+}
+
+pub struct Sacn {
+    socket: UdpSocket,
+    /// Unicast receiver, multicast if None.
+    target: Option<IpAddr>,
+    source: SacnSource,
+    mapping: DmxMapping,
+    sequences: BTreeMap<u16, u8>,
+}
+
+impl Sacn {
+    /// Sends to the given receiver, or via multicast without one.
+    pub fn new(
+        target: Option<&str>,
+        source: SacnSource,
+        mapping: DmxMapping,
+    ) -> Result<Sacn, String> {
+        if let Some(u) = mapping.universes().iter().find(|u| **u == 0 || **u > 63999) {
+            return Err(format!("sACN universes go from 1 to 63999, got {}", u));
+        }
+        let target = match target {
+            Some(address) => Some(
+                address
+                    .parse::<IpAddr>()
+                    .map_err(|_| format!("Invalid sACN address: {}", address))?,
+            ),
+            None => None,
+        };
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+        match target {
+            Some(ip) => info!("Sending sACN to {}.", ip),
+            None => info!("Sending sACN via multicast."),
+        }
+        Ok(Sacn {
+            socket,
+            target,
+            source,
+            mapping,
+            sequences: BTreeMap::new(),
+        })
+    }
+
+    /// Each universe counts on its own and wraps around after 255.
+    fn next_sequence(&mut self, universe: u16) -> u8 {
+        let sequence = self.sequences.entry(universe).or_insert(0);
+        *sequence = sequence.wrapping_add(1);
+        *sequence
+    }
+
+    fn send(&mut self, universe: u16, options: u8, data: &Universe) {
+        let sequence = self.next_sequence(universe);
+        let packet = data_packet(&self.source, sequence, options, universe, data);
+        let ip = self
+            .target
+            .unwrap_or_else(|| IpAddr::V4(multicast_address(universe)));
+        if let Err(e) = self.socket.send_to(&packet, SocketAddr::new(ip, SACN_PORT)) {
+            warn!("Failed to send sACN packet: {}", e);
+        }
+    }
+}
+
+impl OutputBackend for Sacn {
+    fn write_frame(&mut self, frame: &Frame) {
+        for (universe, data) in self.mapping.render(frame) {
+            self.send(universe, 0, &data);
+        }
+    }
+
+    fn set_parameters(&mut self, parameters: &Parameters) {
+        self.mapping.set_parameters(parameters);
+    }
+
+    /// Tells the receivers that the stream ended, so they release the
+    /// universes right away instead of holding the last frame.
+    fn shutdown(&mut self) {
+        info!("Terminating sACN streams.");
+        for universe in self.mapping.universes() {
+            for _ in 0..TERMINATION_PACKETS {
+                self.send(universe, OPTION_STREAM_TERMINATED, &[0; 512]);
+            }
+        }
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.mapping.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(name: &str) -> SacnSource {
+        SacnSource {
+            name: name.to_string(),
+            cid: [0xab; 16],
+            priority: 150,
+        }
+    }
+
+    #[test]
+    fn data_packet_layers() {
+        let mut data = [0; 512];
+        data[0] = 10;
+        data[511] = 20;
+        let packet = data_packet(&source("nightfire"), 7, 0, 0x1234, &data);
+        assert_eq!(packet.len(), 638);
+        // root layer
+        assert_eq!(packet[0..4], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(packet[16..18], (0x7000u16 | 622).to_be_bytes());
+        assert_eq!(packet[18..22], [0, 0, 0, 4]);
+        assert_eq!(packet[22..38], [0xab; 16]);
+        // framing layer
+        assert_eq!(packet[38..40], (0x7000u16 | 600).to_be_bytes());
+        assert_eq!(packet[40..44], [0, 0, 0, 2]);
+        assert_eq!(&packet[44..53], b"nightfire");
+        assert!(packet[53..108].iter().all(|b| *b == 0));
+        assert_eq!(packet[108], 150);
+        assert_eq!(packet[109..111], [0, 0]);
+        assert_eq!(packet[111], 7);
+        assert_eq!(packet[112], 0);
+        assert_eq!(packet[113..115], [0x12, 0x34]);
+        // DMP layer
+        assert_eq!(packet[115..117], (0x7000u16 | 523).to_be_bytes());
+        assert_eq!(packet[117..119], [0x02, 0xa1]);
+        assert_eq!(packet[119..123], [0, 0, 0, 1]);
+        assert_eq!(packet[123..125], 513u16.to_be_bytes());
+        assert_eq!(packet[125], 0);
+        assert_eq!(packet[126], 10);
+        assert_eq!(packet[637], 20);
+    }
+
+    #[test]
+    fn long_names_keep_a_terminating_zero() {
+        let packet = data_packet(&source(&"x".repeat(100)), 1, 0, 1, &[0; 512]);
+        assert!(packet[44..107].iter().all(|b| *b == b'x'));
+        assert_eq!(packet[107], 0);
+    }
+
+    #[test]
+    fn termination_option() {
+        let packet = data_packet(&source("a"), 1, OPTION_STREAM_TERMINATED, 1, &[0; 512]);
+        assert_eq!(packet[112], 0x40);
+    }
+
+    #[test]
+    fn multicast_address_per_universe() {
+        assert_eq!(multicast_address(1), Ipv4Addr::new(239, 255, 0, 1));
+        assert_eq!(
+            multicast_address(0x1234),
+            Ipv4Addr::new(239, 255, 0x12, 0x34)
+        );
+    }
+
+    #[test]
+    fn cid_with_and_without_dashes() {
+        let cid = parse_cid("01234567-89ab-cdef-0123-456789ABCDEF").unwrap();
+        assert_eq!(cid[..4], [0x01, 0x23, 0x45, 0x67]);
+        assert_eq!(cid[15], 0xef);
+        assert_eq!(parse_cid("0123456789abcdef0123456789abcdef").unwrap(), cid);
+        assert!(parse_cid("0123").is_err());
+        assert!(parse_cid("0123456789abcdef0123456789abcdeg").is_err());
+    }
+
+    #[test]
+    fn invalid_address_is_an_error() {
+        assert!(Sacn::new(Some("10.0.0.256"), source("a"), DmxMapping::new(vec![])).is_err());
+        assert!(Sacn::new(Some("::1"), source("a"), DmxMapping::new(vec![])).is_ok());
+        assert!(Sacn::new(None, source("a"), DmxMapping::new(vec![])).is_ok());
+    }
+
+    #[test]
+    fn sequences_per_universe_wrap() {
+        let mut sacn = Sacn::new(Some("127.0.0.1"), source("a"), DmxMapping::new(vec![])).unwrap();
+        sacn.sequences.insert(1, 254);
+        assert_eq!(sacn.next_sequence(1), 255);
+        assert_eq!(sacn.next_sequence(1), 0);
+        assert_eq!(sacn.next_sequence(2), 1);
+        assert_eq!(sacn.next_sequence(1), 1);
+    }
+}
@@ -0,0 +1,295 @@
+//! Timing of the output loop.  The loop ticks at the rate of the
+//! fastest output and computes new frames at the frame rate, outputs
+//! that are slower are paced to their own rate.  The ticks are kept on
+//! a fixed grid, so the timing doesn't drift with the time the frames
+//! take.
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::cmap::Parameters;
+use crate::light::Color;
+use log::info;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often frames are computed and sent.
+#[derive(Debug, Copy, Clone)]
+pub struct FrameTiming {
+    /// Frames computed per second.
+    pub fps: f64,
+    /// Ticks of the output loop per second, the rate of the fastest
+    /// output.
+    pub tick_fps: f64,
+    /// Blend between the last two frames on the ticks in between,
+    /// which delays the outputs by a frame, see `interpolate`.
+    pub interpolate: bool,
+    /// How often the timing is reported in the log, if at all.
+    pub stats: Option<Duration>,
+}
+
+impl Default for FrameTiming {
+    fn default() -> Self {
+        FrameTiming {
+            fps: 50.,
+            tick_fps: 50.,
+            interpolate: false,
+            stats: None,
+        }
+    }
+}
+
+/// Points in time at a fixed rate.
+pub struct Schedule {
+    period: Duration,
+    next: Instant,
+}
+
+impl Schedule {
+    pub fn new(fps: f64) -> Schedule {
+        Schedule {
+            period: Duration::from_secs_f64(1. / fps),
+            next: Instant::now(),
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Steps to the next point, or starts over from `now` if it fell
+    /// behind by more than a period, so missed ones aren't rushed
+    /// through.  Returns whether it fell behind.
+    fn advance(&mut self, now: Instant) -> bool {
+        self.next += self.period;
+        if self.next < now {
+            self.next = now + self.period;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Sleeps until the next point.  Returns how late it woke up and
+    /// whether points were skipped.
+    pub fn wait(&mut self) -> (Duration, bool) {
+        let now = Instant::now();
+        if now < self.next {
+            thread::sleep(self.next - now);
+        }
+        let woke = Instant::now();
+        let late = woke.saturating_duration_since(self.next);
+        (late, self.advance(woke))
+    }
+
+    /// For a schedule that is checked on the ticks of a faster one:
+    /// whether the next point is reached, or within `tolerance`.  Then
+    /// it steps to the following one.
+    pub fn due(&mut self, now: Instant, tolerance: Duration) -> bool {
+        if now + tolerance < self.next {
+            return false;
+        }
+        self.advance(now);
+        true
+    }
+}
+
+/// Collects how well a loop keeps its timing and reports it in the
+/// log at an interval.
+pub struct TimingStats {
+    name: String,
+    interval: Duration,
+    since: Instant,
+    frames: u32,
+    late_sum: Duration,
+    late_max: Duration,
+    work_sum: Duration,
+    work_max: Duration,
+    skipped: u32,
+}
+
+impl TimingStats {
+    pub fn new(name: &str, interval: Duration) -> TimingStats {
+        TimingStats {
+            name: name.to_string(),
+            interval,
+            since: Instant::now(),
+            frames: 0,
+            late_sum: Duration::default(),
+            late_max: Duration::default(),
+            work_sum: Duration::default(),
+            work_max: Duration::default(),
+            skipped: 0,
+        }
+    }
+
+    /// A frame that started `late` and took `work`.
+    pub fn record(&mut self, late: Duration, work: Duration, skipped: bool) {
+        self.frames += 1;
+        self.late_sum += late;
+        self.late_max = self.late_max.max(late);
+        self.work_sum += work;
+        self.work_max = self.work_max.max(work);
+        if skipped {
+            self.skipped += 1;
+        }
+        let elapsed = self.since.elapsed();
+        if elapsed >= self.interval {
+            let ms = |d: Duration| d.as_secs_f64() * 1000.;
+            let n = self.frames;
+            info!(
+                "{}: {:.1} fps, late {:.2} ms on average, {:.2} ms at most, \
+                 work {:.2} ms on average, {:.2} ms at most, fell behind {} times",
+                self.name,
+                n as f64 / elapsed.as_secs_f64(),
+                ms(self.late_sum / n),
+                ms(self.late_max),
+                ms(self.work_sum / n),
+                ms(self.work_max),
+                self.skipped
+            );
+            *self = TimingStats::new(&self.name, self.interval);
+        }
+    }
+}
+
+/// Blends from one frame to the next, t from 0 to 1.  The blend runs
+/// from the previous to the newest frame, so the newest one is only
+/// fully shown a frame after it was computed: interpolating adds a
+/// frame of latency, 20 ms at 50 fps.  Extrapolating instead would
+/// overshoot on every change of direction.
+pub fn interpolate(from: &Frame, to: &Frame, t: f64) -> Vec<(LightId, Vec<Color>)> {
+    let mix = |a: &Color, b: &Color| {
+        Color::new(
+            a.red + (b.red - a.red) * t,
+            a.green + (b.green - a.green) * t,
+            a.blue + (b.blue - a.blue) * t,
+        )
+    };
+    to.iter()
+        .zip(from)
+        .map(|((id, to), (_, from))| {
+            let colors = if from.len() == to.len() {
+                from.iter().zip(to).map(|(a, b)| mix(a, b)).collect()
+            } else {
+                to.clone()
+            };
+            (id.clone(), colors)
+        })
+        .collect()
+}
+
+/// Passes frames on at the backend's own rate, for outputs that are
+/// slower than the output loop.
+pub struct Paced {
+    backend: Box<dyn OutputBackend>,
+    schedule: Schedule,
+    /// Half a tick of the loop, so the rate comes out right on average.
+    tolerance: Duration,
+    stats: Option<TimingStats>,
+}
+
+impl Paced {
+    pub fn new(
+        backend: Box<dyn OutputBackend>,
+        name: &str,
+        fps: f64,
+        timing: &FrameTiming,
+    ) -> Paced {
+        Paced {
+            backend,
+            schedule: Schedule::new(fps),
+            tolerance: Duration::from_secs_f64(0.5 / timing.tick_fps),
+            stats: timing
+                .stats
+                .map(|interval| TimingStats::new(name, interval)),
+        }
+    }
+}
+
+impl OutputBackend for Paced {
+    fn write_frame(&mut self, frame: &Frame) {
+        let now = Instant::now();
+        let due = self.schedule.next;
+        if !self.schedule.due(now, self.tolerance) {
+            return;
+        }
+        self.backend.write_frame(frame);
+        if let Some(stats) = &mut self.stats {
+            stats.record(now.saturating_duration_since(due), now.elapsed(), false);
+        }
+    }
+
+    fn set_parameters(&mut self, parameters: &Parameters) {
+        self.backend.set_parameters(parameters);
+    }
+
+    fn shutdown(&mut self) {
+        self.backend.shutdown();
+    }
+
+    fn steps(&self) -> u32 {
+        self.backend.steps()
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.backend.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schedule_does_not_drift() {
+        let mut schedule = Schedule::new(100.);
+        let start = schedule.next;
+        let period = schedule.period();
+        for i in 0..1000u32 {
+            // wakes up late every time, but never by a whole period
+            let late = Duration::from_micros(u64::from(i % 7) * 1000);
+            assert!(!schedule.advance(schedule.next + late));
+        }
+        assert_eq!(schedule.next, start + period * 1000);
+    }
+
+    #[test]
+    fn schedule_starts_over_when_behind() {
+        let mut schedule = Schedule::new(100.);
+        let now = schedule.next + schedule.period() * 3;
+        assert!(schedule.advance(now));
+        assert_eq!(schedule.next, now + schedule.period());
+    }
+
+    #[test]
+    fn due_on_the_ticks_of_a_faster_schedule() {
+        let mut schedule = Schedule::new(25.);
+        let start = schedule.next;
+        let tick = Duration::from_millis(10);
+        // the ticks are up to 2 ms late
+        let tick_at = |i: u32| start + tick * i + Duration::from_millis(u64::from(i % 3));
+        let due: Vec<u32> = (0..100u32)
+            .filter(|i| schedule.due(tick_at(*i), tick / 2))
+            .collect();
+        assert_eq!(due, (0..100).step_by(4).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn interpolates_between_frames() {
+        let id = LightId::get("Top");
+        let from = vec![(id.clone(), vec![Color::new(0., 1., 0.)])];
+        let to = vec![(id.clone(), vec![Color::new(1., 0.5, 0.)])];
+        let mixed = interpolate(&from, &to, 0.25);
+        assert_eq!(mixed[0].0, id);
+        let color = mixed[0].1[0];
+        assert_eq!((color.red, color.green, color.blue), (0.25, 0.875, 0.));
+        assert_eq!(interpolate(&from, &to, 1.)[0].1, to[0].1);
+    }
+
+    #[test]
+    fn interpolate_takes_the_new_frame_if_the_pixels_differ() {
+        let id = LightId::get("Strip");
+        let from = vec![(id.clone(), vec![Color::new(0., 0., 0.)])];
+        let to = vec![(id, vec![Color::new(1., 1., 1.); 2])];
+        assert_eq!(interpolate(&from, &to, 0.5)[0].1, to[0].1);
+    }
+}
@@ -0,0 +1,170 @@
+//! Serial output for strips driven by a microcontroller on USB, like
+//! an Arduino running an Adalight or TPM2 sketch.  The port is opened
+//! again if it disappears, e.g. when the cable is pulled.
+use super::pixels::{ColorOrder, PixelMapping};
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::LightId;
+use crate::light::Color;
+use log::{info, warn};
+use serialport::SerialPort;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+const REOPEN_INTERVAL: Duration = Duration::from_secs(2);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How a frame is wrapped on the wire.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Framing {
+    Adalight,
+    Tpm2,
+}
+
+impl FromStr for Framing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "adalight" => Ok(Framing::Adalight),
+            "tpm2" => Ok(Framing::Tpm2),
+            other => Err(format!("Unknown serial framing: {}", other)),
+        }
+    }
+}
+
+impl Framing {
+    /// Wraps the pixel data, 3 bytes per pixel.
+    pub fn frame(&self, data: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(data.len() + 6);
+        match self {
+            Framing::Adalight => {
+                // "Ada", the pixel count - 1 and a checksum
+                let [hi, lo] = ((data.len() / 3).saturating_sub(1) as u16).to_be_bytes();
+                buf.extend_from_slice(b"Ada");
+                buf.extend_from_slice(&[hi, lo, hi ^ lo ^ 0x55]);
+                buf.extend_from_slice(data);
+            }
+            Framing::Tpm2 => {
+                // start byte, data frame type, byte count, data, end byte
+                buf.extend_from_slice(&[0xc9, 0xda]);
+                buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                buf.extend_from_slice(data);
+                buf.push(0x36);
+            }
+        }
+        buf
+    }
+}
+
+pub struct Serial {
+    path: String,
+    baud_rate: u32,
+    framing: Framing,
+    color_order: ColorOrder,
+    mapping: PixelMapping,
+    port: Option<Box<dyn SerialPort>>,
+    t_last_attempt: Option<Instant>,
+}
+
+impl Serial {
+    pub fn new(
+        path: &str,
+        baud_rate: u32,
+        framing: Framing,
+        color_order: ColorOrder,
+        mapping: PixelMapping,
+    ) -> Serial {
+        Serial {
+            path: path.to_string(),
+            baud_rate,
+            framing,
+            color_order,
+            mapping,
+            port: None,
+            t_last_attempt: None,
+        }
+    }
+
+    fn port(&mut self) -> Option<&mut Box<dyn SerialPort>> {
+        let may_retry = self
+            .t_last_attempt
+            .is_none_or(|t| t.elapsed() > REOPEN_INTERVAL);
+        if self.port.is_none() && may_retry {
+            self.t_last_attempt = Some(Instant::now());
+            match serialport::new(&self.path, self.baud_rate)
+                .timeout(WRITE_TIMEOUT)
+                .open()
+            {
+                Ok(port) => {
+                    info!("Opened {} at {} baud.", self.path, self.baud_rate);
+                    self.port = Some(port);
+                }
+                Err(e) => warn!("Failed to open {}: {}", self.path, e),
+            }
+        }
+        self.port.as_mut()
+    }
+
+    fn send(&mut self, mut data: Vec<u8>) {
+        self.color_order.apply(&mut data);
+        let message = self.framing.frame(&data);
+        if let Some(port) = self.port() {
+            if let Err(e) = port.write_all(&message) {
+                warn!("Lost {}: {}", self.path, e);
+                self.port = None;
+            }
+        }
+    }
+}
+
+impl OutputBackend for Serial {
+    fn write_frame(&mut self, frame: &Frame) {
+        let data = self.mapping.render(frame);
+        self.send(data);
+    }
+
+    fn shutdown(&mut self) {
+        self.send(vec![0; self.mapping.n_pixels() * 3]);
+    }
+
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        self.mapping.duty(id, color)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adalight_header_and_checksum() {
+        let data = vec![7; 300 * 3];
+        let message = Framing::Adalight.frame(&data);
+        assert_eq!(message.len(), 6 + 900);
+        assert_eq!(&message[..3], b"Ada");
+        // the pixel count - 1, big endian
+        assert_eq!(message[3..5], [0x01, 0x2b]);
+        assert_eq!(message[5], 0x01 ^ 0x2b ^ 0x55);
+        assert_eq!(message[6..], data[..]);
+    }
+
+    #[test]
+    fn adalight_single_pixel() {
+        let message = Framing::Adalight.frame(&[1, 2, 3]);
+        assert_eq!(message, [b'A', b'd', b'a', 0, 0, 0x55, 1, 2, 3]);
+    }
+
+    #[test]
+    fn tpm2_frame() {
+        let message = Framing::Tpm2.frame(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(message, [0xc9, 0xda, 0, 6, 1, 2, 3, 4, 5, 6, 0x36]);
+    }
+
+    #[test]
+    fn framing_names() {
+        assert_eq!("adalight".parse(), Ok(Framing::Adalight));
+        assert_eq!("tpm2".parse(), Ok(Framing::Tpm2));
+        assert!("dmx".parse::<Framing>().is_err());
+    }
+}
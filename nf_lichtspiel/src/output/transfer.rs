@@ -0,0 +1,187 @@
+//! The transfer stage between the color map and a backend.  A curve
+//! maps the color values to LED brightness, so that even steps in
+//! the modes look even on the lights, then the calibration of each
+//! light is applied.  Temporal dithering carries the rounding error
+//! of each frame over to the next, which smooths fades at low levels
+//! where the steps of the output are visible.
+use super::calibration::Calibration;
+use super::{Duty, Frame, OutputBackend};
+use crate::layout::{Layout, LightId};
+use crate::light::cmap::Parameters;
+use crate::light::{Color, PinValue};
+use splines::{Interpolation, Key, Spline};
+use std::collections::HashMap;
+
+/// Maps a value in [0, 1] to the brightness that is put out.
+#[derive(Clone, Default)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// `value^gamma`, 2.2 to 2.8 suits most LEDs.
+    Gamma(PinValue),
+    /// Takes the value as CIE lightness, L* from 0 to 100.
+    Cie,
+    /// Through the given (value, brightness) points.
+    Spline(Spline<PinValue, PinValue>),
+}
+
+impl Curve {
+    /// A curve through the (value, brightness) points, linear or
+    /// smooth in between.
+    pub fn spline(mut points: Vec<(PinValue, PinValue)>, smooth: bool) -> Result<Curve, String> {
+        if points.len() < 2 {
+            return Err("A curve needs at least two points".to_string());
+        }
+        points.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let interpolation = if smooth {
+            Interpolation::Cosine
+        } else {
+            Interpolation::Linear
+        };
+        Ok(Curve::Spline(Spline::from_vec(
+            points
+                .iter()
+                .map(|(x, y)| Key::new(*x, *y, interpolation))
+                .collect(),
+        )))
+    }
+
+    pub fn apply(&self, value: PinValue) -> PinValue {
+        let value = value.clamp(0., 1.);
+        match self {
+            Curve::Linear => value,
+            Curve::Gamma(gamma) => value.powf(*gamma),
+            Curve::Cie => {
+                let l = value * 100.;
+                if l <= 8. {
+                    l / 903.3
+                } else {
+                    ((l + 16.) / 116.).powi(3)
+                }
+            }
+            Curve::Spline(spline) => spline.clamped_sample(value).unwrap_or(value).clamp(0., 1.),
+        }
+    }
+
+    pub fn apply_color(&self, color: &Color) -> Color {
+        Color::new(
+            self.apply(color.red),
+            self.apply(color.green),
+            self.apply(color.blue),
+        )
+    }
+}
+
+/// Rounds every channel to the steps of the output and keeps what was
+/// rounded away for the next frame.
+struct Dither {
+    steps: PinValue,
+    errors: HashMap<LightId, Vec<[PinValue; 3]>>,
+}
+
+impl Dither {
+    fn new(steps: u32) -> Dither {
+        Dither {
+            steps: steps.max(1) as PinValue,
+            errors: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, id: &LightId, colors: &mut [Color]) {
+        let steps = self.steps;
+        let errors = self.errors.entry(id.clone()).or_default();
+        errors.resize(colors.len(), [0.; 3]);
+        for (color, error) in colors.iter_mut().zip(errors.iter_mut()) {
+            let wanted = [
+                color.red + error[0],
+                color.green + error[1],
+                color.blue + error[2],
+            ];
+            let rounded = wanted.map(|v| ((v * steps).round() / steps).clamp(0., 1.));
+            *color = Color::new(rounded[0], rounded[1], rounded[2]);
+            *error = [
+                wanted[0] - rounded[0],
+                wanted[1] - rounded[1],
+                wanted[2] - rounded[2],
+            ];
+        }
+    }
+}
+
+/// Runs the frames through a curve, the calibration of the lights and
+/// optionally dithering, before they go to the backend.
+pub struct Transfer {
+    backend: Box<dyn OutputBackend>,
+    curve: Curve,
+    /// Only the lights that have a calibration.
+    calibrations: HashMap<LightId, Calibration>,
+    dither: Option<Dither>,
+}
+
+impl Transfer {
+    pub fn new(
+        backend: Box<dyn OutputBackend>,
+        curve: Curve,
+        layout: &Layout,
+        dither: bool,
+    ) -> Transfer {
+        let calibrations = layout
+            .all()
+            .iter()
+            .filter(|light| !light.calibration.is_identity())
+            .map(|light| (light.id.clone(), light.calibration))
+            .collect();
+        let dither = if dither {
+            Some(Dither::new(backend.steps()))
+        } else {
+            None
+        };
+        Transfer {
+            backend,
+            curve,
+            calibrations,
+            dither,
+        }
+    }
+}
+
+impl OutputBackend for Transfer {
+    fn write_frame(&mut self, frame: &Frame) {
+        let mut frame = frame.to_vec();
+        for (id, colors) in frame.iter_mut() {
+            let calibration = self.calibrations.get(id);
+            for color in colors.iter_mut() {
+                *color = self.curve.apply_color(color);
+                if let Some(calibration) = calibration {
+                    *color = calibration.apply(color);
+                }
+            }
+            if let Some(dither) = &mut self.dither {
+                dither.apply(id, colors);
+            }
+        }
+        self.backend.write_frame(&frame);
+    }
+
+    fn set_parameters(&mut self, parameters: &Parameters) {
+        self.backend.set_parameters(parameters);
+    }
+
+    fn shutdown(&mut self) {
+        self.backend.shutdown();
+    }
+
+    fn steps(&self) -> u32 {
+        self.backend.steps()
+    }
+
+    /// With the curve and the calibration, dithering doesn't change
+    /// the brightness on average.
+    fn duty(&self, id: &LightId, color: &Color) -> Option<Duty> {
+        let mut color = self.curve.apply_color(color);
+        if let Some(calibration) = self.calibrations.get(id) {
+            color = calibration.apply(&color);
+        }
+        self.backend.duty(id, &color)
+    }
+}
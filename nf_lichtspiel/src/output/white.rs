@@ -0,0 +1,108 @@
+//! Lights with white LEDs next to r, g and b.  The part of a color
+//! that the white LEDs can make goes to them, which gives cleaner
+//! whites and pastels than mixing them from r, g and b.
+use crate::light::color::ColorsExt;
+use crate::light::{Color, PinValue};
+
+/// The white LEDs of a light, as the r, g and b they look like at
+/// full brightness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WhiteLeds {
+    Single(Color),
+    /// A warm and a cold white, mixed for the color temperature in
+    /// between.
+    Tunable {
+        warm: Color,
+        cold: Color,
+    },
+}
+
+impl Default for WhiteLeds {
+    /// A single white that is as white as r, g and b together.
+    fn default() -> Self {
+        WhiteLeds::Single(Color::white())
+    }
+}
+
+/// How finely the mix of a tunable white is searched.
+const MIX_STEPS: usize = 32;
+
+/// How much of `white` fits into the color.
+fn amount(color: &Color, white: &Color) -> PinValue {
+    [
+        (color.red, white.red),
+        (color.green, white.green),
+        (color.blue, white.blue),
+    ]
+    .iter()
+    .filter(|(_, w)| *w > 0.)
+    .map(|(c, w)| c.max(0.) / w)
+    .fold(PinValue::INFINITY, PinValue::min)
+    .min(1.)
+}
+
+fn mix(warm: &Color, cold: &Color, t: PinValue) -> Color {
+    Color::new(
+        warm.red * (1. - t) + cold.red * t,
+        warm.green * (1. - t) + cold.green * t,
+        warm.blue * (1. - t) + cold.blue * t,
+    )
+}
+
+impl WhiteLeds {
+    /// A single white of the color temperature in Kelvin.
+    pub fn single(kelvin: f64) -> WhiteLeds {
+        WhiteLeds::Single(Color::kelvin(kelvin))
+    }
+
+    pub fn tunable(warm: f64, cold: f64) -> WhiteLeds {
+        WhiteLeds::Tunable {
+            warm: Color::kelvin(warm),
+            cold: Color::kelvin(cold),
+        }
+    }
+
+    /// Splits the color into what is left for r, g and b and the
+    /// brightness of the white LEDs, warm and cold for a tunable white
+    /// or only the first for a single one.  Without r, g and b LEDs the
+    /// whites are as bright as the color, in the temperature that fits
+    /// it best.
+    pub fn split(&self, color: &Color, with_rgb: bool) -> (Color, [PinValue; 2]) {
+        let (whites, white) = match self {
+            WhiteLeds::Single(white) => {
+                let a = amount(color, white);
+                ([a, 0.], *white)
+            }
+            WhiteLeds::Tunable { warm, cold } => {
+                // the mix that takes the most out of the color
+                let (t, a) = (0..=MIX_STEPS)
+                    .map(|i| {
+                        let t = i as PinValue / MIX_STEPS as PinValue;
+                        (t, amount(color, &mix(warm, cold, t)))
+                    })
+                    .fold((0., -1.), |best, m| if m.1 > best.1 { m } else { best });
+                ([a * (1. - t), a * t], mix(warm, cold, t))
+            }
+        };
+        if !with_rgb {
+            let max = |c: &Color| c.red.max(c.green).max(c.blue);
+            let level = if max(&white) > 0. {
+                (max(color) / max(&white)).clamp(0., 1.)
+            } else {
+                0.
+            };
+            // colors without white in them, like a pure red, get the
+            // warm white
+            let total = whites[0] + whites[1];
+            let warm = if total > 0. { whites[0] / total } else { 1. };
+            return (Color::black(), [level * warm, level * (1. - warm)]);
+        }
+        let used = white.mask(whites[0] + whites[1]);
+        let rest = Color::new(
+            (color.red - used.red).max(0.),
+            (color.green - used.green).max(0.),
+            (color.blue - used.blue).max(0.),
+        );
+        (rest, whites)
+    }
+}
@@ -1,71 +1,58 @@
-use crate::lightid::LightId;
-use nightfire::light as li;
-use nightfire::light::cmap::ColorMap;
+use crate::layout::Layout;
+use crate::light::cmap::ColorMap;
+use crate::light::Color;
+use crate::output::light_colors;
+use crate::output::transfer::Curve;
+use palette::Srgb;
 use piston_window::*;
 
-/// more realistic light intensity
-fn fix_int(val: f32) -> f32 {
-    ((1. - val).powi(2) * -1.) + 1.
-}
-
-fn piston_color(color_map: &Box<dyn ColorMap + Send + Sync>, pos: &li::Coordinate) -> [f32; 4] {
-    let color = color_map.get_color(&pos);
+/// The color goes through the same curve as on the outputs, which
+/// gives the brightness of the LEDs.  The screen expects sRGB, so the
+/// brightness is encoded for it.
+fn piston_color(curve: &Curve, color: &Color) -> [f32; 4] {
+    let screen = Srgb::from_linear(curve.apply_color(color));
     [
-        fix_int(color.red as f32),
-        fix_int(color.green as f32),
-        fix_int(color.blue as f32),
+        screen.red as f32,
+        screen.green as f32,
+        screen.blue as f32,
         1.0,
     ]
 }
 
-/// creates a transformation for one of the stripes
-fn get_transf(context: Context, rot: f64, w: f64, n: f64) -> [[f64; 3]; 2] {
-    context
-        .transform
-        .trans(n, n)
-        .rot_deg(rot)
-        .trans(w * -0.5, w * 0.5)
-}
-
-pub fn run_piston_thread(color_map: Box<dyn ColorMap + Send + Sync>) {
+pub fn run_piston_thread(
+    color_map: Box<dyn ColorMap + Send + Sync>,
+    layout: Layout,
+    curve: Curve,
+) {
     println!("Startin window thread!");
     let n = 200.;
-    let w = 50.;
+    let r = 25.;
 
     let mut window: PistonWindow = WindowSettings::new("lumi debug simulation UI", [n * 2.; 2])
         .exit_on_esc(true)
         .build()
         .unwrap();
 
-    let stripe = [0.0, 0.0, w, n];
     while let Some(e) = window.next() {
         window.draw_2d(&e, |c, g, _| {
             clear([0.5, 0.5, 0.5, 1.0], g);
 
-            rectangle(
-                piston_color(&color_map, &LightId::Top.pos()),
-                stripe,
-                get_transf(c, 135., w, n),
-                g,
-            );
-            rectangle(
-                piston_color(&color_map, &LightId::Bottom.pos()),
-                stripe,
-                get_transf(c, -45., w, n),
-                g,
-            );
-            rectangle(
-                piston_color(&color_map, &LightId::Left.pos()),
-                stripe,
-                get_transf(c, 45., w, n),
-                g,
-            );
-            rectangle(
-                piston_color(&color_map, &LightId::Right.pos()),
-                stripe,
-                get_transf(c, -135., w, n),
-                g,
-            );
+            // a dot per pixel, the coordinate space goes from -1 to 1
+            // and y points up
+            for light in layout.all() {
+                let r = if light.pixels.len() > 1 { r / 4. } else { r };
+                let colors = light_colors(light, color_map.as_ref());
+                for (pos, color) in light.pixels.iter().zip(&colors) {
+                    let x = n + pos.0 * (n - r);
+                    let y = n - pos.1 * (n - r);
+                    ellipse(
+                        piston_color(&curve, color),
+                        [x - r, y - r, r * 2., r * 2.],
+                        c.transform,
+                        g,
+                    );
+                }
+            }
         });
     }
 }
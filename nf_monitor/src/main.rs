@@ -1,14 +1,21 @@
 pub mod monitor;
 mod ui;
 use clap::{App, Arg};
-use nf_audio::CpalAudioGetter;
+use nf_audio::{list_devices, open_source};
 
 fn main() {
     // argparsing
     let matches = App::new("lumi")
         .arg(Arg::with_name("q").short("q").takes_value(true))
         .arg(Arg::with_name("n").short("n").takes_value(true))
+        .arg(Arg::with_name("input").long("input").takes_value(true))
+        .arg(Arg::with_name("list-devices").long("list-devices"))
+        .arg(Arg::with_name("json").long("json").requires("list-devices"))
         .get_matches();
+    if matches.is_present("list-devices") {
+        list_devices(matches.is_present("json"));
+        return;
+    }
     let q = matches
         .value_of("q")
         .map(|v| v.parse().unwrap())
@@ -18,7 +25,7 @@ fn main() {
         .map(|v| v.parse().unwrap())
         .unwrap_or(30);
     // open audio client
-    let mut audio_getter = CpalAudioGetter::new("default".to_string());
+    let mut audio_getter = open_source(matches.value_of("input").unwrap_or("default"));
     let sample_rate = audio_getter.get_sample_rate();
     let mut monitor = monitor::SoundMonitor::new(sample_rate, q, n_filters);
     let data = monitor.get_shared_vals();
@@ -1,4 +1,4 @@
-use nf_audio::CpalAudioGetter;
+use nf_audio::open_source;
 use nf_audio::ValsHandler;
 use nf_audio::list_devices;
 use nightfire::audio::{SignalProcessor, AudioEvent2 as AudioEvent, EdgeID};
@@ -90,6 +90,12 @@ impl ValsHandler for OSCPublisher {
 struct Opts {
     #[clap(short, long, parse(from_occurrences))]
     verbose: i32,
+    /// Lists the audio inputs and exits, same as the list subcommand.
+    #[clap(long)]
+    list_devices: bool,
+    /// With --list-devices, print the devices as JSON.
+    #[clap(long)]
+    json: bool,
     #[clap(subcommand)]
     subcommand: Option<SubCommand>,
 }
@@ -101,22 +107,37 @@ enum SubCommand {
 }
 
 #[derive(Clap)]
-struct ListCommand { }
+struct ListCommand {
+    /// Print the devices with their capabilities as JSON.
+    #[clap(long)]
+    json: bool,
+}
 
 #[derive(Clap)]
 struct RunCommand {
-    device: String
+    device: Option<String>,
+    /// Audio input, e.g. file:track.wav or stdin:44100:2:s16le.
+    /// Takes precedence over the device.
+    #[clap(long)]
+    input: Option<String>,
 }
 
-fn run(device_name: String) {
+fn run(input: String) {
     info!("Initializing Audio Settings.");
-    let mut audio_getter = CpalAudioGetter::new(device_name);
+    let mut audio_getter = open_source(&input);
     let sample_rate = audio_getter.get_sample_rate();
     let publisher = OSCPublisher::new(sample_rate);
     info!("Opening Audio Input Stream.");
-    let stream = audio_getter.start_processing(Box::new(publisher));
-    info!("Waiting for input, press [ENTER] to terminate ...");
-    let line: String = read!("{}quit\r\n");
+    audio_getter.start_processing(Box::new(publisher));
+    if input.starts_with("file") || input.starts_with("stdin") {
+        // headless, run until the input ends
+        while !audio_getter.is_finished() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    } else {
+        info!("Waiting for input, press [ENTER] to terminate ...");
+        let line: String = read!("{}quit\r\n");
+    }
     info!("Terminating Audio Processing.");
     audio_getter.stop_processing();
 }
@@ -124,9 +145,17 @@ fn run(device_name: String) {
 fn main() {
     env_logger::init();
     let opts = Opts::parse();
+    if opts.list_devices {
+        list_devices(opts.json);
+        return;
+    }
     match opts.subcommand {
-        Some(SubCommand::List(l)) => list_devices(),
-        Some(SubCommand::Run(r)) => run(r.device),
+        Some(SubCommand::List(l)) => list_devices(l.json),
+        Some(SubCommand::Run(r)) => run(
+            r.input
+                .or(r.device)
+                .unwrap_or_else(|| "default".to_string()),
+        ),
         None => run("Voicemeeter Virtual ASIO".to_string()),
     }
 }
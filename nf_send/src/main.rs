@@ -0,0 +1,60 @@
+//! Captures audio and streams it to a `udp:` source of nightfire on
+//! another machine.
+use clap::{App, Arg};
+use nf_audio::{list_devices, open_source, NetworkSender};
+use std::{thread, time};
+
+fn main() {
+    env_logger::init();
+    let matches = App::new("nf_send")
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required_unless("list-devices")
+                .help("Where to send the audio, e.g. 192.168.1.10:5005"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .takes_value(true)
+                .default_value("default"),
+        )
+        .arg(
+            Arg::with_name("channel")
+                .long("channel")
+                .takes_value(true)
+                .help("Channel to send, or 'downmix'"),
+        )
+        .arg(
+            Arg::with_name("sample-rate")
+                .long("sample-rate")
+                .takes_value(true)
+                .help("Resample to this rate before sending"),
+        )
+        .arg(Arg::with_name("list-devices").long("list-devices"))
+        .arg(Arg::with_name("json").long("json").requires("list-devices"))
+        .get_matches();
+    if matches.is_present("list-devices") {
+        list_devices(matches.is_present("json"));
+        return;
+    }
+    let mut audio_getter = open_source(matches.value_of("input").unwrap());
+    if let Some(channel) = matches.value_of("channel") {
+        audio_getter.set_channel_selection(channel.parse().unwrap());
+    }
+    audio_getter.set_target_sample_rate(
+        matches
+            .value_of("sample-rate")
+            .map(|r| r.parse().expect("Invalid sample rate")),
+    );
+    let sender = NetworkSender::new(
+        matches.value_of("to").unwrap(),
+        audio_getter.get_sample_rate(),
+    );
+    audio_getter.start_processing(Box::new(sender));
+    while !audio_getter.is_finished() {
+        thread::sleep(time::Duration::from_millis(100));
+    }
+    audio_getter.stop_processing();
+}
@@ -14,6 +14,18 @@ impl FilterID {
     pub fn get(s: &str) -> Self {
         Self(s.to_string())
     }
+
+    /// The ID of the percussive part of this band, see
+    /// HarmonicPercussiveSeparator.
+    pub fn percussive(&self) -> Self {
+        Self(format!("{}.percussive", self.0))
+    }
+
+    /// The ID of the harmonic part of this band, see
+    /// HarmonicPercussiveSeparator.
+    pub fn harmonic(&self) -> Self {
+        Self(format!("{}.harmonic", self.0))
+    }
 }
 
 pub enum FilterParams {
@@ -0,0 +1,189 @@
+use crate::audio::FilterID;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+fn median(mut vals: Vec<f32>) -> f32 {
+    vals.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let n = vals.len();
+    // for odd n both indices are the same
+    (vals[(n - 1) / 2] + vals[n / 2]) / 2.
+}
+
+/// Parameters for the harmonic/percussive separation.  The bands need
+/// to be given in ascending frequency order, because the percussive
+/// part is found by looking at neighbouring bands.
+#[derive(Clone)]
+pub struct HpssParams {
+    pub bands: Vec<FilterID>,
+    /// Number of frames (including the current one) used for the
+    /// median across time.
+    pub time_kernel: usize,
+    /// Number of neighbouring bands used for the median across
+    /// frequency.  Should be odd.
+    pub freq_kernel: usize,
+    /// Exponent of the soft masks.  Higher values give a harder
+    /// separation, 2 is a good default.
+    pub mask_power: f32,
+}
+
+/// A streaming harmonic/percussive separation on band values.
+///
+/// Sustained sounds (pads, bass lines) are stable over time, but
+/// narrow in frequency.  Hits are short, but broadband.  The harmonic
+/// estimate is the median of the band history.  The percussive
+/// estimate is the median across neighbouring bands of how far each
+/// band currently rises above its harmonic estimate.  Looking at the
+/// rise instead of the plain value keeps loud sustained bands that
+/// sit next to each other from counting as broadband.  Both estimates
+/// are turned into soft masks which split the band value into a
+/// percussive and a harmonic part.  The two parts always sum up to
+/// the original value.
+///
+/// Only past frames are used, so a new sustained tone counts as
+/// percussive for the first half of the time kernel.
+pub struct HarmonicPercussiveSeparator {
+    bands: Vec<FilterID>,
+    percussive_ids: Vec<FilterID>,
+    harmonic_ids: Vec<FilterID>,
+    freq_kernel: usize,
+    mask_power: f32,
+    /// The band history, newest frame at the front.
+    hist: VecDeque<Vec<f32>>,
+}
+
+impl HarmonicPercussiveSeparator {
+    pub fn new(params: &HpssParams) -> Self {
+        let n = params.bands.len();
+        Self {
+            bands: params.bands.clone(),
+            percussive_ids: params.bands.iter().map(|id| id.percussive()).collect(),
+            harmonic_ids: params.bands.iter().map(|id| id.harmonic()).collect(),
+            freq_kernel: params.freq_kernel.max(1),
+            mask_power: params.mask_power,
+            hist: VecDeque::from(vec![vec![0.; n]; params.time_kernel.max(1)]),
+        }
+    }
+
+    /// Takes the band values of a single frame, in the order of the
+    /// bands.  Returns the percussive and the harmonic band values.
+    pub fn separate(&mut self, vals: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        self.hist.push_front(vals.to_vec());
+        self.hist.pop_back();
+        let n = vals.len();
+        let half_kernel = self.freq_kernel / 2;
+        let harmonic_est: Vec<f32> = (0..n)
+            .map(|i| median(self.hist.iter().map(|frame| frame[i]).collect()))
+            .collect();
+        let rise: Vec<f32> = (0..n)
+            .map(|i| (vals[i] - harmonic_est[i]).max(0.))
+            .collect();
+        let mut percussive = Vec::with_capacity(n);
+        let mut harmonic = Vec::with_capacity(n);
+        for i in 0..n {
+            let h = harmonic_est[i];
+            let i_start = i.saturating_sub(half_kernel);
+            let i_end = (i + half_kernel + 1).min(n);
+            let p = median(rise[i_start..i_end].to_vec());
+            let h_w = h.max(0.).powf(self.mask_power);
+            let p_w = p.max(0.).powf(self.mask_power);
+            let p_mask = if h_w + p_w > 0. { p_w / (h_w + p_w) } else { 0.5 };
+            percussive.push(vals[i] * p_mask);
+            harmonic.push(vals[i] * (1. - p_mask));
+        }
+        (percussive, harmonic)
+    }
+
+    /// Like `separate`, but works on the filter value map of the
+    /// FilterFT.  The result contains the percussive and harmonic
+    /// value of each band, under the IDs returned by
+    /// `FilterID::percussive` and `FilterID::harmonic`.  Bands that
+    /// are missing in the map count as silent.
+    pub fn update(&mut self, filter_vals: &HashMap<FilterID, f32>) -> HashMap<FilterID, f32> {
+        let vals: Vec<f32> = self
+            .bands
+            .iter()
+            .map(|id| filter_vals.get(id).copied().unwrap_or(0.))
+            .collect();
+        let (percussive, harmonic) = self.separate(&vals);
+        let mut res = HashMap::new();
+        for i in 0..self.bands.len() {
+            res.insert(self.percussive_ids[i].clone(), percussive[i]);
+            res.insert(self.harmonic_ids[i].clone(), harmonic[i]);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn separator(n_bands: usize) -> HarmonicPercussiveSeparator {
+        HarmonicPercussiveSeparator::new(&HpssParams {
+            bands: (0..n_bands)
+                .map(|i| FilterID::get(&format!("band{}", i)))
+                .collect(),
+            time_kernel: 9,
+            freq_kernel: 3,
+            mask_power: 2.,
+        })
+    }
+
+    #[test]
+    fn median_of_odd_and_even_counts() {
+        assert_eq!(median(vec![3., 1., 2.]), 2.);
+        assert_eq!(median(vec![4., 1., 3., 2.]), 2.5);
+    }
+
+    #[test]
+    fn steady_tone_is_harmonic() {
+        let mut hpss = separator(5);
+        let tone = [0., 0., 1., 0., 0.];
+        let mut parts = (vec![], vec![]);
+        for _ in 0..20 {
+            parts = hpss.separate(&tone);
+        }
+        let (percussive, harmonic) = parts;
+        assert!(percussive[2] < 0.01);
+        assert!(harmonic[2] > 0.99);
+    }
+
+    #[test]
+    fn click_is_percussive() {
+        let mut hpss = separator(5);
+        for _ in 0..20 {
+            hpss.separate(&[0.1; 5]);
+        }
+        let (percussive, harmonic) = hpss.separate(&[1.; 5]);
+        for i in 0..5 {
+            assert!(percussive[i] > 0.9, "band {}: {}", i, percussive[i]);
+            assert!((percussive[i] + harmonic[i] - 1.).abs() < 1e-6);
+        }
+        // and the click is over at once
+        let (percussive, _) = hpss.separate(&[0.1; 5]);
+        assert!(percussive.iter().all(|p| *p < 0.01));
+    }
+
+    #[test]
+    fn loud_neighbouring_tones_stay_harmonic() {
+        let mut hpss = separator(5);
+        let chord = [0., 1., 1., 1., 0.];
+        let mut parts = (vec![], vec![]);
+        for _ in 0..20 {
+            parts = hpss.separate(&chord);
+        }
+        assert!(parts.0[2] < 0.01);
+    }
+
+    #[test]
+    fn missing_bands_count_as_silent() {
+        let mut hpss = separator(2);
+        let mut vals = HashMap::new();
+        vals.insert(FilterID::get("band0"), 1.);
+        let res = hpss.update(&vals);
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[&FilterID::get("band1").percussive()], 0.);
+        assert_eq!(res[&FilterID::get("band1").harmonic()], 0.);
+    }
+}
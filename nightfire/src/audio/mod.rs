@@ -19,7 +19,9 @@ mod audio_features;
 mod edge_detector;
 mod filter;
 mod filter_ft;
+mod hpss;
 pub mod intensity;
+mod preprocessor;
 mod processors;
 mod sample_handler;
 mod signal_processor;
@@ -33,6 +35,8 @@ pub use edge_detector::{EdgeDetectorParams, EdgeDetectors, EdgeID, EdgeEvent};
 pub use filter::FilterFreqs;
 pub use filter::SignalFilter;
 pub use filter_ft::{BandPassParams, FilterFT, FilterID, FilterParams};
+pub use hpss::{HarmonicPercussiveSeparator, HpssParams};
+pub use preprocessor::{HumNotchParams, NoiseGateParams, Preprocessor, PreprocessorParams};
 pub use processors::primitives::NormalizedDecayingValue;
 pub use processors::running_stats::RunningStats;
 pub use sample_handler::default_sample_handler::{CollectSampleHandler, DefaultSampleHandler};
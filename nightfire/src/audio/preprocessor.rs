@@ -0,0 +1,305 @@
+use biquad as bq;
+use biquad::Biquad;
+
+/// A first order highpass that removes a constant offset from the
+/// signal.
+pub struct DcBlocker {
+    r: f32,
+    x_prev: f32,
+    y_prev: f32,
+}
+
+impl DcBlocker {
+    /// f_s is the sample rate, f_c the cutoff frequency.  The cutoff
+    /// should be well below the lowest band of interest (20Hz).
+    pub fn new(f_s: f32, f_c: f32) -> Self {
+        Self {
+            r: 1. - (2. * std::f32::consts::PI * f_c / f_s),
+            x_prev: 0.,
+            y_prev: 0.,
+        }
+    }
+
+    pub fn run(&mut self, x: f32) -> f32 {
+        let y = x - self.x_prev + self.r * self.y_prev;
+        self.x_prev = x;
+        self.y_prev = y;
+        y
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct HumNotchParams {
+    /// The mains frequency, 50 or 60 Hz.
+    pub f_mains: f32,
+    /// How many multiples of the mains frequency are filtered,
+    /// including the fundamental.
+    pub harmonics: usize,
+    pub q: f32,
+}
+
+/// A chain of notch filters at the mains frequency and its
+/// harmonics.  Harmonics above the nyquist frequency, or that the
+/// filter can't be computed for, are skipped.
+pub struct HumNotch {
+    filters: Vec<bq::DirectForm2Transposed<f32>>,
+}
+
+impl HumNotch {
+    pub fn new(f_s: f32, params: &HumNotchParams) -> Self {
+        let filters = (1..=params.harmonics)
+            .map(|k| params.f_mains * k as f32)
+            .filter(|f_c| *f_c < f_s / 2.)
+            .filter_map(|f_c| {
+                let coefficients = bq::Coefficients::<f32>::from_params(
+                    bq::Type::Notch,
+                    bq::Hertz::<f32>::from_hz(f_s).ok()?,
+                    bq::Hertz::<f32>::from_hz(f_c).ok()?,
+                    params.q,
+                );
+                Some(bq::DirectForm2Transposed::<f32>::new(coefficients.ok()?))
+            })
+            .collect();
+        Self { filters }
+    }
+
+    pub fn run(&mut self, x: f32) -> f32 {
+        let mut y = x;
+        for filter in self.filters.iter_mut() {
+            y = filter.run(y);
+        }
+        y
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NoiseGateParams {
+    /// Level in dBFS below which the gate closes.
+    pub threshold: f32,
+    /// Time in seconds to open the gate.
+    pub attack: f32,
+    /// Time in seconds to close the gate.
+    pub release: f32,
+}
+
+/// How far in dB the level has to fall below the threshold to close
+/// the gate again, so a level right at the threshold doesn't make it
+/// flutter.
+const GATE_HYSTERESIS: f32 = 6.;
+
+/// Mutes the signal while its level is below a threshold.  The level
+/// is tracked with an envelope follower, the gain is faded in and out
+/// so the gate doesn't click.  The gate opens above the threshold and
+/// closes GATE_HYSTERESIS below it.
+pub struct NoiseGate {
+    threshold: f32,
+    close_threshold: f32,
+    env_decay: f32,
+    attack_step: f32,
+    release_step: f32,
+    env: f32,
+    gain: f32,
+    open: bool,
+}
+
+impl NoiseGate {
+    pub fn new(f_s: f32, params: &NoiseGateParams) -> Self {
+        Self {
+            threshold: 10f32.powf(params.threshold / 20.),
+            close_threshold: 10f32.powf((params.threshold - GATE_HYSTERESIS) / 20.),
+            env_decay: (-1. / (params.release.max(0.001) * f_s)).exp(),
+            attack_step: 1. / (params.attack.max(0.0001) * f_s),
+            release_step: 1. / (params.release.max(0.001) * f_s),
+            env: 0.,
+            gain: 0.,
+            open: false,
+        }
+    }
+
+    pub fn run(&mut self, x: f32) -> f32 {
+        self.env = x.abs().max(self.env * self.env_decay);
+        if self.env > self.threshold {
+            self.open = true;
+        } else if self.env < self.close_threshold {
+            self.open = false;
+        }
+        if self.open {
+            self.gain = (self.gain + self.attack_step).min(1.);
+        } else {
+            self.gain = (self.gain - self.release_step).max(0.);
+        }
+        x * self.gain
+    }
+}
+
+/// Which conditioning steps the Preprocessor applies.  The default
+/// applies none.
+#[derive(Clone, Debug, Default)]
+pub struct PreprocessorParams {
+    pub bypass: bool,
+    pub dc_block: bool,
+    pub hum_notch: Option<HumNotchParams>,
+    pub noise_gate: Option<NoiseGateParams>,
+}
+
+/// Input conditioning in front of the FilterFT.  Removes DC offset
+/// and mains hum, which would otherwise show up in the low bands, and
+/// gates the noise floor of the input.  The steps are applied in
+/// that order.  A bypassed Preprocessor passes the signal through
+/// unchanged, but keeps the filters running so switching is smooth.
+pub struct Preprocessor {
+    bypass: bool,
+    dc_blocker: Option<DcBlocker>,
+    hum_notch: Option<HumNotch>,
+    noise_gate: Option<NoiseGate>,
+}
+
+impl Preprocessor {
+    pub fn new(f_s: f32, params: &PreprocessorParams) -> Self {
+        Self {
+            bypass: params.bypass,
+            dc_blocker: if params.dc_block {
+                Some(DcBlocker::new(f_s, 5.))
+            } else {
+                None
+            },
+            hum_notch: params.hum_notch.as_ref().map(|p| HumNotch::new(f_s, p)),
+            noise_gate: params.noise_gate.as_ref().map(|p| NoiseGate::new(f_s, p)),
+        }
+    }
+
+    pub fn set_bypass(&mut self, bypass: bool) {
+        self.bypass = bypass;
+    }
+
+    pub fn switch_bypass(&mut self) {
+        self.bypass = !self.bypass;
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.bypass
+    }
+
+    pub fn run(&mut self, x: f32) -> f32 {
+        let mut y = x;
+        if let Some(dc_blocker) = &mut self.dc_blocker {
+            y = dc_blocker.run(y);
+        }
+        if let Some(hum_notch) = &mut self.hum_notch {
+            y = hum_notch.run(y);
+        }
+        if let Some(noise_gate) = &mut self.noise_gate {
+            y = noise_gate.run(y);
+        }
+        if self.bypass {
+            x
+        } else {
+            y
+        }
+    }
+
+    pub fn process_frame(&mut self, audio_frame: &[f32]) -> Vec<f32> {
+        audio_frame.iter().map(|x| self.run(*x)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const F_S: f32 = 8000.;
+
+    fn sine(freq: f32, secs: f32) -> Vec<f32> {
+        (0..(secs * F_S) as usize)
+            .map(|i| (2. * PI * freq * i as f32 / F_S).sin())
+            .collect()
+    }
+
+    /// The RMS of the second half, after the filters settled.
+    fn settled_rms(vals: &[f32]) -> f32 {
+        let tail = &vals[vals.len() / 2..];
+        (tail.iter().map(|v| v * v).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    fn hum_notch() -> HumNotch {
+        let params = HumNotchParams {
+            f_mains: 50.,
+            harmonics: 4,
+            q: 30.,
+        };
+        HumNotch::new(F_S, &params)
+    }
+
+    #[test]
+    fn dc_offset_decays() {
+        let mut dc_blocker = DcBlocker::new(F_S, 5.);
+        let out: Vec<f32> = (0..2 * F_S as usize).map(|_| dc_blocker.run(0.5)).collect();
+        assert!(out[0] > 0.4);
+        assert!(out[out.len() - 1].abs() < 0.001);
+    }
+
+    #[test]
+    fn hum_and_harmonics_are_attenuated() {
+        let sine_rms = settled_rms(&sine(50., 2.));
+        for freq in &[50., 100., 150., 200.] {
+            let mut notch = hum_notch();
+            let out: Vec<f32> = sine(*freq, 2.).iter().map(|x| notch.run(*x)).collect();
+            assert!(settled_rms(&out) < 0.05 * sine_rms, "{} Hz", freq);
+        }
+    }
+
+    #[test]
+    fn nearby_frequencies_pass() {
+        for freq in &[40., 75., 125., 300.] {
+            let input = sine(*freq, 2.);
+            let mut notch = hum_notch();
+            let out: Vec<f32> = input.iter().map(|x| notch.run(*x)).collect();
+            assert!(settled_rms(&out) > 0.9 * settled_rms(&input), "{} Hz", freq);
+        }
+    }
+
+    #[test]
+    fn harmonics_above_nyquist_are_skipped() {
+        let params = HumNotchParams {
+            f_mains: 60.,
+            harmonics: 8,
+            q: 30.,
+        };
+        assert_eq!(HumNotch::new(250., &params).filters.len(), 2);
+    }
+
+    fn gate() -> NoiseGate {
+        let params = NoiseGateParams {
+            threshold: -40.,
+            attack: 0.001,
+            release: 0.01,
+        };
+        NoiseGate::new(F_S, &params)
+    }
+
+    /// Runs a constant level for 0.1 seconds, returns the last output.
+    fn hold(gate: &mut NoiseGate, level: f32) -> f32 {
+        (0..(0.1 * F_S) as usize).fold(0., |_, _| gate.run(level))
+    }
+
+    #[test]
+    fn gate_opens_and_closes() {
+        let mut gate = gate();
+        assert_eq!(hold(&mut gate, 0.001), 0.);
+        assert_eq!(hold(&mut gate, 0.1), 0.1);
+        assert_eq!(hold(&mut gate, 0.001), 0.);
+    }
+
+    #[test]
+    fn gate_has_hysteresis() {
+        // -40 dBFS opens, -46 dBFS closes, in between nothing changes
+        let between = 0.007;
+        let mut gate = gate();
+        assert_eq!(hold(&mut gate, between), 0.);
+        assert_eq!(hold(&mut gate, 0.02), 0.02);
+        assert_eq!(hold(&mut gate, between), between);
+        assert_eq!(hold(&mut gate, 0.004), 0.);
+        assert_eq!(hold(&mut gate, between), 0.);
+    }
+}
@@ -1,7 +1,8 @@
 use crate::audio::intensity;
 use crate::audio::{
     BandPassParams, EdgeDetectorParams, EdgeDetectors, EdgeEvent, EdgeID, FilterFT, FilterID,
-    FilterParams, PhraseDetector2 as PhraseDetector, PhraseEvent, SilenceDetector, SilenceEvent,
+    FilterParams, HarmonicPercussiveSeparator, HpssParams, PhraseDetector2 as PhraseDetector,
+    PhraseEvent, SilenceDetector, SilenceEvent,
 };
 use std::collections::HashMap;
 
@@ -43,6 +44,22 @@ fn default_filter_params() -> HashMap<FilterID, FilterParams> {
     res
 }
 
+fn default_hpss_params() -> HpssParams {
+    HpssParams {
+        bands: vec![
+            FilterID::get("band_20_3"),
+            FilterID::get("band_130_3"),
+            FilterID::get("band_310_3"),
+            FilterID::get("brilliance1_3"),
+            FilterID::get("brilliance2_3"),
+            FilterID::get("brilliance3_3"),
+        ],
+        time_kernel: 17,
+        freq_kernel: 3,
+        mask_power: 2.,
+    }
+}
+
 fn default_intensity_params() -> HashMap<intensity::IntensityID, intensity::IntensityParams> {
     let mut res = HashMap::new();
     res.insert(
@@ -101,6 +118,37 @@ fn default_intensity_params() -> HashMap<intensity::IntensityID, intensity::Inte
             },
         ),
     );
+    res.insert(
+        intensity::IntensityID::get("bass_percussive"),
+        intensity::IntensityParams(
+            intensity::IntensityInputParams::TakeMax(vec![
+                FilterID::get("band_20_3").percussive(),
+                FilterID::get("band_130_3").percussive(),
+                FilterID::get("band_310_3").percussive(),
+            ]),
+            intensity::NormalizedDecayingParams {
+                decay_factor: 0.005,
+                decay_val_for_max: 0.01666,
+            },
+        ),
+    );
+    res.insert(
+        intensity::IntensityID::get("harmonic"),
+        intensity::IntensityParams(
+            intensity::IntensityInputParams::TakeMax(vec![
+                FilterID::get("band_20_3").harmonic(),
+                FilterID::get("band_130_3").harmonic(),
+                FilterID::get("band_310_3").harmonic(),
+                FilterID::get("brilliance1_3").harmonic(),
+                FilterID::get("brilliance2_3").harmonic(),
+                FilterID::get("brilliance3_3").harmonic(),
+            ]),
+            intensity::NormalizedDecayingParams {
+                decay_factor: 0.5,
+                decay_val_for_max: 0.01666,
+            },
+        ),
+    );
     res.insert(
         intensity::IntensityID::get("highs"),
         intensity::IntensityParams(
@@ -123,7 +171,7 @@ fn default_edge_params() -> HashMap<EdgeID, EdgeDetectorParams> {
     res.insert(
         EdgeID::get("bass"),
         EdgeDetectorParams {
-            source_intensity: intensity::IntensityID::get("bass"),
+            source_intensity: intensity::IntensityID::get("bass_percussive"),
             sensitivity: 0.3,
         },
     );
@@ -146,9 +194,24 @@ pub enum AudioEvent {
     PhraseEnded,
 }
 
+impl AudioEvent {
+    /// A short name like `onset/bass`, used when events are logged.
+    /// Intensities change every frame and have no label.
+    pub fn label(&self) -> Option<String> {
+        match self {
+            AudioEvent::Intensities(_) => None,
+            AudioEvent::Onset(edge_id) => Some(format!("onset/{}", edge_id.0)),
+            AudioEvent::SilenceStarted => Some("silence/started".to_string()),
+            AudioEvent::SilenceEnded => Some("silence/ended".to_string()),
+            AudioEvent::PhraseEnded => Some("phrase/ended".to_string()),
+        }
+    }
+}
+
 pub struct SignalProcessor {
     time_delta: f32,
     filter_ft: FilterFT,
+    hpss: HarmonicPercussiveSeparator,
     intensity_trackers: intensity::IntensityTrackers,
     edge_detectors: EdgeDetectors,
     silence_detector: SilenceDetector,
@@ -161,6 +224,7 @@ impl SignalProcessor {
         Self {
             time_delta: 1. / fps,
             filter_ft: FilterFT::new(sample_freq, window_size, &default_filter_params()),
+            hpss: HarmonicPercussiveSeparator::new(&default_hpss_params()),
             intensity_trackers: intensity::IntensityTrackers::new(&default_intensity_params()),
             edge_detectors: EdgeDetectors::new(&default_edge_params()),
             silence_detector: SilenceDetector::new(FilterID::get("no_filter")),
@@ -171,7 +235,10 @@ impl SignalProcessor {
     pub fn add_audio_frame(&mut self, audio_frame: &[f32]) -> Vec<AudioEvent> {
         let mut events = Vec::new();
         for x in audio_frame {
-            if let Some(ft_vec) = self.filter_ft.update(*x) {
+            if let Some(mut ft_vec) = self.filter_ft.update(*x) {
+                // the percussive and harmonic parts are added as extra bands
+                let hpss_vals = self.hpss.update(&ft_vec);
+                ft_vec.extend(hpss_vals);
                 let silence_event = self.silence_detector.update(self.time_delta, &ft_vec);
                 let intensities = self.intensity_trackers.update(self.time_delta, &ft_vec);
                 let edge_events = self.edge_detectors.update(self.time_delta, &intensities);